@@ -0,0 +1,153 @@
+use crate::coordinator::{ControlError, JobStatus, Shared};
+use crate::metrics;
+use crate::worker::WorkerStatus;
+use anyhow::{Context, Result};
+use axum::extract::{Path, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tracing::info;
+
+/// One resolved config key and the layer it came from.
+#[derive(Debug, Serialize)]
+struct ConfigEntry {
+    value: Value,
+    source: String,
+}
+
+/// Serves the control API:
+///
+/// - `GET /status`, `GET /workers/{rank}`: job and per-rank status
+/// - `POST /workers/{rank}/restart|kill|drain`: act on one rank
+/// - `POST /stop`: stop the job gracefully
+/// - `POST /world_size`: relaunch the job with `{"world_size": N}` ranks
+/// - `POST /checkpoint`: take a global checkpoint across all ranks
+/// - `GET /config`: resolved config with the source of every key
+/// - `GET /metrics`: Prometheus metrics
+pub async fn serve(shared: Arc<Shared>, addr: SocketAddr) -> Result<()> {
+    let app = Router::new()
+        .route("/status", get(status))
+        .route("/workers/{rank}", get(worker_status))
+        .route("/workers/{rank}/restart", post(restart))
+        .route("/workers/{rank}/kill", post(kill))
+        .route("/workers/{rank}/drain", post(drain))
+        .route("/stop", post(stop))
+        .route("/world_size", post(world_size))
+        .route("/checkpoint", post(checkpoint))
+        .route("/config", get(config))
+        .route("/metrics", get(prometheus))
+        .with_state(shared);
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("failed to bind control API on {}", addr))?;
+    info!("[coord] control API on http://{}", addr);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+async fn status(State(shared): State<Arc<Shared>>) -> Json<JobStatus> {
+    Json(shared.status())
+}
+
+async fn worker_status(
+    State(shared): State<Arc<Shared>>,
+    Path(rank): Path<usize>,
+) -> Result<Json<WorkerStatus>, ControlError> {
+    shared
+        .status()
+        .workers
+        .into_iter()
+        .find(|w| w.rank == rank)
+        .map(Json)
+        .ok_or(ControlError::UnknownRank(rank))
+}
+
+async fn restart(
+    State(shared): State<Arc<Shared>>,
+    Path(rank): Path<usize>,
+) -> Result<StatusCode, ControlError> {
+    shared.restart_rank(rank, "requested").await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+async fn kill(
+    State(shared): State<Arc<Shared>>,
+    Path(rank): Path<usize>,
+) -> Result<StatusCode, ControlError> {
+    shared.kill_rank(rank)?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+async fn drain(
+    State(shared): State<Arc<Shared>>,
+    Path(rank): Path<usize>,
+) -> Result<StatusCode, ControlError> {
+    shared.drain_rank(rank)?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+async fn stop(State(shared): State<Arc<Shared>>) -> StatusCode {
+    shared.request_stop();
+    StatusCode::ACCEPTED
+}
+
+#[derive(Debug, Deserialize)]
+struct WorldSize {
+    world_size: usize,
+}
+
+async fn world_size(
+    State(shared): State<Arc<Shared>>,
+    Json(body): Json<WorldSize>,
+) -> Result<StatusCode, ControlError> {
+    shared.request_rescale(body.world_size)?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+async fn checkpoint(State(shared): State<Arc<Shared>>) -> Result<StatusCode, ControlError> {
+    shared.request_checkpoint()?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+async fn config(State(shared): State<Arc<Shared>>) -> Json<BTreeMap<String, ConfigEntry>> {
+    let Ok(Value::Object(values)) = serde_json::to_value(&shared.config) else {
+        return Json(BTreeMap::new());
+    };
+    let entries = values
+        .into_iter()
+        .map(|(key, value)| {
+            let source = shared
+                .config_sources
+                .get(&key)
+                .map(|s| s.to_string())
+                .unwrap_or_else(|| "default".to_string());
+            (key, ConfigEntry { value, source })
+        })
+        .collect();
+    Json(entries)
+}
+
+async fn prometheus(State(shared): State<Arc<Shared>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics::render(&shared),
+    )
+}
+
+impl IntoResponse for ControlError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            ControlError::UnknownRank(_) => StatusCode::NOT_FOUND,
+            ControlError::Conflict(_) => StatusCode::CONFLICT,
+            ControlError::Spawn(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status, self.to_string()).into_response()
+    }
+}
@@ -0,0 +1,484 @@
+use crate::checkpoint;
+use crate::config::Config;
+use crate::coordinator::Generation;
+use crate::state::write_atomic;
+use crate::worker::WorkerState;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// `checkpoint_dir/<job>/GLOBAL_LATEST`: the last complete global checkpoint.
+pub fn global_latest_path(checkpoint_dir: &Path, job_id: &str) -> PathBuf {
+    checkpoint_dir.join(job_id).join("GLOBAL_LATEST")
+}
+
+/// The job's last complete global checkpoint, if it has one.
+pub fn read_global_latest(checkpoint_dir: &Path, job_id: &str) -> Result<Option<GlobalCheckpoint>> {
+    let path = global_latest_path(checkpoint_dir, job_id);
+    match std::fs::read_to_string(&path) {
+        Ok(content) => Ok(Some(serde_json::from_str(&content).with_context(|| {
+            format!("invalid global checkpoint in {}", path.display())
+        })?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("failed to read {}", path.display())),
+    }
+}
+
+/// `checkpoint_dir/<job>/BARRIER`: the barrier workers are asked to join.
+pub fn announcement_path(checkpoint_dir: &Path, job_id: &str) -> PathBuf {
+    checkpoint_dir.join(job_id).join("BARRIER")
+}
+
+/// Written by a rank into its worker directory once its step directory for
+/// the barrier is committed.
+const ACK_FILE: &str = "BARRIER_ACK";
+
+/// A set of per-rank checkpoints taken at the same barrier, listed in
+/// `GLOBAL_LATEST`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GlobalCheckpoint {
+    pub epoch: u64,
+    pub generation: u64,
+    pub world_size: usize,
+    pub committed_at: f64,
+    /// Committed step of every rank.
+    pub steps: BTreeMap<usize, u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Phase {
+    /// Ranks checkpoint, acknowledge, and wait.
+    Pending,
+    Committed,
+    /// Ranks carry on without a global checkpoint.
+    Aborted,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Announcement {
+    epoch: u64,
+    phase: Phase,
+    /// Ranks stop waiting after this even if the coordinator went away.
+    deadline: f64,
+}
+
+#[derive(Debug, Deserialize)]
+struct Ack {
+    epoch: u64,
+    step: u64,
+}
+
+/// The barrier in progress, as reported in the job status.
+#[derive(Debug, Clone, Serialize)]
+pub struct BarrierStatus {
+    pub epoch: u64,
+    pub started_at: f64,
+    /// Ranks whose checkpoint for it is committed.
+    pub acked: Vec<usize>,
+}
+
+/// What a poll of the barrier did.
+#[derive(Debug)]
+pub enum BarrierEvent {
+    Started {
+        epoch: u64,
+    },
+    Committed {
+        checkpoint: GlobalCheckpoint,
+        duration_secs: f64,
+    },
+    Aborted {
+        epoch: u64,
+        reason: String,
+    },
+}
+
+struct Active {
+    epoch: u64,
+    generation: Generation,
+    started_at: f64,
+    deadline: f64,
+    steps: BTreeMap<usize, u64>,
+}
+
+/// Coordinator side of the global checkpoint barrier.
+///
+/// Every `global_checkpoint_interval` seconds (or on request) the next
+/// epoch is announced in `BARRIER`. Each running rank checkpoints at its
+/// next step boundary, acknowledges the committed step, and waits. Once
+/// every rank has, the steps are written to `GLOBAL_LATEST` in one atomic
+/// replace and the ranks are released. A rank that fails, or does not
+/// acknowledge within `barrier_timeout`, aborts the barrier and the previous
+/// global checkpoint stays in place.
+pub struct CheckpointBarrier {
+    checkpoint_dir: PathBuf,
+    job_id: String,
+    interval: Option<f64>,
+    timeout: f64,
+    /// Last epoch announced.
+    epoch: u64,
+    next_at: f64,
+    requested: bool,
+    active: Option<Active>,
+    latest: Option<GlobalCheckpoint>,
+}
+
+impl CheckpointBarrier {
+    /// Picks up the job's last global checkpoint. A barrier a previous
+    /// coordinator left pending is aborted so its ranks stop waiting.
+    pub fn open(config: &Config, now: f64) -> Result<Self> {
+        let checkpoint_dir = PathBuf::from(&config.checkpoint_dir);
+        let latest = read_global_latest(&checkpoint_dir, &config.job_id)?;
+        let interval = (config.global_checkpoint_interval > 0)
+            .then_some(config.global_checkpoint_interval as f64);
+        let mut barrier = Self {
+            checkpoint_dir,
+            job_id: config.job_id.clone(),
+            interval,
+            timeout: config.barrier_timeout as f64,
+            epoch: latest.as_ref().map_or(0, |c| c.epoch),
+            next_at: now + interval.unwrap_or_default(),
+            requested: false,
+            active: None,
+            latest,
+        };
+        if let Some(previous) = barrier.read_announcement() {
+            barrier.epoch = barrier.epoch.max(previous.epoch);
+            if previous.phase == Phase::Pending {
+                warn!(
+                    "[coord] abandoning global checkpoint {} left pending by a previous run",
+                    previous.epoch
+                );
+                barrier.announce(previous.epoch, Phase::Aborted, previous.deadline);
+            }
+        }
+        if let Some(c) = &barrier.latest {
+            info!(
+                "[coord] last global checkpoint: epoch {} (generation {})",
+                c.epoch, c.generation
+            );
+        }
+        Ok(barrier)
+    }
+
+    /// The last complete global checkpoint.
+    pub fn latest(&self) -> Option<&GlobalCheckpoint> {
+        self.latest.as_ref()
+    }
+
+    pub fn status(&self) -> Option<BarrierStatus> {
+        self.active.as_ref().map(|a| BarrierStatus {
+            epoch: a.epoch,
+            started_at: a.started_at,
+            acked: a.steps.keys().copied().collect(),
+        })
+    }
+
+    /// Step a (re)started rank resumes from: its step in the last global
+    /// checkpoint, if that was taken in the current generation.
+    pub fn resume_step(&self, generation: Generation, rank: usize) -> Option<u64> {
+        let latest = self
+            .latest
+            .as_ref()
+            .filter(|c| c.generation == generation.number)?;
+        latest.steps.get(&rank).copied()
+    }
+
+    /// Starts a barrier at the next poll that finds every rank running.
+    pub fn request(&mut self) -> Result<(), String> {
+        if let Some(active) = &self.active {
+            return Err(format!("global checkpoint {} is in progress", active.epoch));
+        }
+        self.requested = true;
+        Ok(())
+    }
+
+    /// Gives up on the barrier in progress, if any, releasing its ranks.
+    pub fn abort(&mut self, reason: &str) -> Option<BarrierEvent> {
+        let active = self.active.take()?;
+        self.announce(active.epoch, Phase::Aborted, active.deadline);
+        Some(BarrierEvent::Aborted {
+            epoch: active.epoch,
+            reason: reason.to_string(),
+        })
+    }
+
+    /// Advances the barrier given the state of every rank: starts one when
+    /// due, collects acknowledgements, and commits or aborts it. Reads and
+    /// writes files, so it is called without holding the worker lock.
+    pub fn poll(
+        &mut self,
+        now: f64,
+        generation: Generation,
+        ranks: &[(usize, WorkerState)],
+    ) -> Option<BarrierEvent> {
+        let Some(active) = &mut self.active else {
+            return self.maybe_start(now, generation, ranks);
+        };
+        if active.generation != generation {
+            return self.abort("world size changed");
+        }
+        for &(rank, state) in ranks {
+            if active.steps.contains_key(&rank) {
+                continue;
+            }
+            let dir = checkpoint::worker_dir(&self.checkpoint_dir, &self.job_id, rank);
+            match state {
+                WorkerState::Running => {
+                    if let Some(ack) = read_ack(&dir).filter(|a| a.epoch == active.epoch) {
+                        active.steps.insert(rank, ack.step);
+                    }
+                }
+                // Its final checkpoint is where it stays.
+                WorkerState::Succeeded => match checkpoint::latest_step(&dir) {
+                    Some(step) => {
+                        active.steps.insert(rank, step);
+                    }
+                    None => {
+                        return self.abort(&format!("rank {} finished without a checkpoint", rank))
+                    }
+                },
+                state => return self.abort(&format!("rank {} is {}", rank, state)),
+            }
+        }
+
+        if active.steps.len() == generation.world_size {
+            return self.commit(now);
+        }
+        if now >= active.deadline {
+            let missing: Vec<String> = (0..generation.world_size)
+                .filter(|r| !active.steps.contains_key(r))
+                .map(|r| r.to_string())
+                .collect();
+            return self.abort(&format!(
+                "timed out waiting for ranks {}",
+                missing.join(",")
+            ));
+        }
+        None
+    }
+
+    fn maybe_start(
+        &mut self,
+        now: f64,
+        generation: Generation,
+        ranks: &[(usize, WorkerState)],
+    ) -> Option<BarrierEvent> {
+        let due = self.requested || self.interval.is_some_and(|_| now >= self.next_at);
+        // A rank that is restarting cannot take part; try again next poll.
+        let ready = ranks.len() == generation.world_size
+            && ranks
+                .iter()
+                .all(|(_, s)| matches!(s, WorkerState::Running | WorkerState::Succeeded))
+            && ranks.iter().any(|(_, s)| *s == WorkerState::Running);
+        if !due || !ready {
+            return None;
+        }
+        self.epoch += 1;
+        self.requested = false;
+        self.next_at = now + self.interval.unwrap_or_default();
+        let deadline = now + self.timeout;
+        self.announce(self.epoch, Phase::Pending, deadline);
+        self.active = Some(Active {
+            epoch: self.epoch,
+            generation,
+            started_at: now,
+            deadline,
+            steps: BTreeMap::new(),
+        });
+        Some(BarrierEvent::Started { epoch: self.epoch })
+    }
+
+    fn commit(&mut self, now: f64) -> Option<BarrierEvent> {
+        let active = self.active.take()?;
+        for (&rank, &step) in &active.steps {
+            let dir = checkpoint::worker_dir(&self.checkpoint_dir, &self.job_id, rank)
+                .join(format!("step_{}", step));
+            if !dir.is_dir() {
+                self.active = Some(active);
+                return self.abort(&format!("step_{} of rank {} is missing", step, rank));
+            }
+        }
+        let checkpoint = GlobalCheckpoint {
+            epoch: active.epoch,
+            generation: active.generation.number,
+            world_size: active.generation.world_size,
+            committed_at: now,
+            steps: active.steps,
+        };
+        let path = global_latest_path(&self.checkpoint_dir, &self.job_id);
+        let written = serde_json::to_vec_pretty(&checkpoint)
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| write_atomic(&path, &bytes));
+        if let Err(e) = written {
+            self.announce(active.epoch, Phase::Aborted, active.deadline);
+            return Some(BarrierEvent::Aborted {
+                epoch: active.epoch,
+                reason: format!("{:#}", e),
+            });
+        }
+        self.announce(active.epoch, Phase::Committed, active.deadline);
+        self.latest = Some(checkpoint.clone());
+        Some(BarrierEvent::Committed {
+            checkpoint,
+            duration_secs: now - active.started_at,
+        })
+    }
+
+    fn read_announcement(&self) -> Option<Announcement> {
+        let content =
+            std::fs::read_to_string(announcement_path(&self.checkpoint_dir, &self.job_id)).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    fn announce(&self, epoch: u64, phase: Phase, deadline: f64) {
+        let path = announcement_path(&self.checkpoint_dir, &self.job_id);
+        let result = serde_json::to_vec(&Announcement {
+            epoch,
+            phase,
+            deadline,
+        })
+        .map_err(anyhow::Error::from)
+        .and_then(|bytes| write_atomic(&path, &bytes));
+        if let Err(e) = result {
+            warn!(
+                "[coord] failed to announce global checkpoint {}: {:#}",
+                epoch, e
+            );
+        }
+    }
+}
+
+fn read_ack(worker_dir: &Path) -> Option<Ack> {
+    let content = std::fs::read_to_string(worker_dir.join(ACK_FILE)).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const GEN: Generation = Generation {
+        number: 0,
+        world_size: 2,
+    };
+
+    const RUNNING: [(usize, WorkerState); 2] = [(0, WorkerState::Running), (1, WorkerState::Running)];
+
+    fn open(dir: &TempDir, now: f64) -> CheckpointBarrier {
+        let config = Config {
+            checkpoint_dir: dir.path().display().to_string(),
+            job_id: "job".to_string(),
+            barrier_timeout: 60,
+            ..Config::default()
+        };
+        CheckpointBarrier::open(&config, now).unwrap()
+    }
+
+    /// Commits `step_<step>` of `rank` and acknowledges it for `epoch`.
+    fn ack(dir: &TempDir, rank: usize, epoch: u64, step: u64) {
+        let worker_dir = checkpoint::worker_dir(dir.path(), "job", rank);
+        std::fs::create_dir_all(worker_dir.join(format!("step_{}", step))).unwrap();
+        let ack = format!("{{\"epoch\": {}, \"step\": {}}}", epoch, step);
+        std::fs::write(worker_dir.join(ACK_FILE), ack).unwrap();
+    }
+
+    fn start(barrier: &mut CheckpointBarrier, now: f64) -> u64 {
+        barrier.request().unwrap();
+        match barrier.poll(now, GEN, &RUNNING) {
+            Some(BarrierEvent::Started { epoch }) => epoch,
+            other => panic!("expected a start, got {:?}", other),
+        }
+    }
+
+    fn phase(barrier: &CheckpointBarrier) -> Phase {
+        barrier.read_announcement().unwrap().phase
+    }
+
+    #[test]
+    fn commits_once_every_rank_has_acked() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut barrier = open(&dir, 0.0);
+        let epoch = start(&mut barrier, 0.0);
+        assert_eq!(epoch, 1);
+        assert!(barrier.request().is_err());
+
+        ack(&dir, 0, epoch, 10);
+        // An acknowledgement of an earlier barrier does not count.
+        ack(&dir, 1, epoch - 1, 8);
+        assert!(barrier.poll(1.0, GEN, &RUNNING).is_none());
+        assert_eq!(barrier.status().unwrap().acked, [0]);
+
+        ack(&dir, 1, epoch, 12);
+        let checkpoint = match barrier.poll(2.0, GEN, &RUNNING) {
+            Some(BarrierEvent::Committed { checkpoint, .. }) => checkpoint,
+            other => panic!("expected a commit, got {:?}", other),
+        };
+        assert_eq!(checkpoint.steps, BTreeMap::from([(0, 10), (1, 12)]));
+        assert_eq!(phase(&barrier), Phase::Committed);
+        assert!(barrier.status().is_none());
+        assert_eq!(barrier.latest(), Some(&checkpoint));
+    }
+
+    #[test]
+    fn aborts_on_timeout_or_a_lost_rank() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut barrier = open(&dir, 0.0);
+        let epoch = start(&mut barrier, 0.0);
+        ack(&dir, 0, epoch, 10);
+        assert!(matches!(
+            barrier.poll(60.0, GEN, &RUNNING),
+            Some(BarrierEvent::Aborted { .. })
+        ));
+        assert_eq!(phase(&barrier), Phase::Aborted);
+
+        start(&mut barrier, 61.0);
+        let ranks = [(0, WorkerState::Running), (1, WorkerState::Failed)];
+        assert!(matches!(
+            barrier.poll(62.0, GEN, &ranks),
+            Some(BarrierEvent::Aborted { .. })
+        ));
+        assert_eq!(phase(&barrier), Phase::Aborted);
+        assert!(barrier.latest().is_none());
+    }
+
+    #[test]
+    fn resume_step_comes_from_the_last_committed_barrier() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut barrier = open(&dir, 0.0);
+        assert_eq!(barrier.resume_step(GEN, 0), None);
+        let epoch = start(&mut barrier, 0.0);
+        ack(&dir, 0, epoch, 10);
+        ack(&dir, 1, epoch, 12);
+        barrier.poll(1.0, GEN, &RUNNING).unwrap();
+
+        // Survives a coordinator restart, but not a world size change.
+        let barrier = open(&dir, 2.0);
+        assert_eq!(barrier.resume_step(GEN, 1), Some(12));
+        let next = Generation {
+            number: 1,
+            world_size: 3,
+        };
+        assert_eq!(barrier.resume_step(next, 1), None);
+    }
+
+    #[test]
+    fn reopening_aborts_a_half_finished_barrier() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut barrier = open(&dir, 0.0);
+        let epoch = start(&mut barrier, 0.0);
+        ack(&dir, 0, epoch, 10);
+        drop(barrier);
+
+        let mut barrier = open(&dir, 1.0);
+        assert_eq!(phase(&barrier), Phase::Aborted);
+        assert!(barrier.status().is_none());
+        assert!(barrier.latest().is_none());
+        // The next barrier does not reuse the abandoned epoch.
+        assert_eq!(start(&mut barrier, 1.0), epoch + 1);
+    }
+}
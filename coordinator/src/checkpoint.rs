@@ -0,0 +1,493 @@
+use crate::barrier;
+use crate::coordinator::Generation;
+use crate::state::{self, write_atomic};
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// `checkpoint_dir/<job>/worker_<rank>`, where a rank keeps its step
+/// directories, `LATEST` pointer, and `HEARTBEAT` file.
+pub fn worker_dir(checkpoint_dir: &Path, job_id: &str, rank: usize) -> PathBuf {
+    checkpoint_dir.join(job_id).join(format!("worker_{}", rank))
+}
+
+/// Parses a worker directory name (`worker_<rank>`).
+pub fn parse_worker_dir(name: &str) -> Option<usize> {
+    name.strip_prefix("worker_")?.parse().ok()
+}
+
+/// `worker_<rank>` directories of a job, by rank.
+pub fn worker_dirs(job_dir: &Path) -> io::Result<BTreeMap<usize, PathBuf>> {
+    let mut ranks = BTreeMap::new();
+    let entries = match std::fs::read_dir(job_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(ranks),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if let Some(rank) = parse_worker_dir(&name) {
+            if entry.file_type()?.is_dir() {
+                ranks.insert(rank, entry.path());
+            }
+        }
+    }
+    Ok(ranks)
+}
+
+/// Parses a committed step directory name (`step_<N>`).
+pub fn parse_step_dir(name: &str) -> Option<u64> {
+    name.strip_prefix("step_")?.parse().ok()
+}
+
+/// Step named by the rank's `LATEST` pointer, if any.
+pub fn latest_step(worker_dir: &Path) -> Option<u64> {
+    let latest = std::fs::read_to_string(worker_dir.join("LATEST")).ok()?;
+    parse_step_dir(latest.trim())
+}
+
+/// The `state.json` of the checkpoint the rank's `LATEST` points to.
+pub fn latest_state(worker_dir: &Path) -> Option<serde_json::Value> {
+    let latest = std::fs::read_to_string(worker_dir.join("LATEST")).ok()?;
+    let content = std::fs::read_to_string(worker_dir.join(latest.trim()).join("state.json")).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+/// Committed step directories of a rank, in ascending order.
+pub fn list_steps(worker_dir: &Path) -> Vec<u64> {
+    let Ok(entries) = std::fs::read_dir(worker_dir) else {
+        return Vec::new();
+    };
+    let mut steps: Vec<u64> = entries
+        .flatten()
+        .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+        .filter_map(|e| parse_step_dir(&e.file_name().to_string_lossy()))
+        .collect();
+    steps.sort_unstable();
+    steps
+}
+
+/// Why a step directory cannot be resumed from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Invalid {
+    Missing,
+    /// `manifest.json` is missing, unreadable, or not `committed: true`.
+    NotCommitted,
+    BadState(String),
+    WrongRank(Option<u64>),
+    /// Written at another world size in the current generation.
+    WrongWorldSize(Option<u64>),
+}
+
+impl fmt::Display for Invalid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Invalid::Missing => f.write_str("missing"),
+            Invalid::NotCommitted => f.write_str("not committed"),
+            Invalid::BadState(e) => write!(f, "unreadable state.json ({})", e),
+            Invalid::WrongRank(r) => write!(f, "state.json is for rank {:?}", r),
+            Invalid::WrongWorldSize(n) => write!(f, "state.json is for world_size {:?}", n),
+        }
+    }
+}
+
+/// Checks that `step_<step>` of a rank is a committed checkpoint that
+/// `rank` can resume from. A checkpoint of the `current` generation must
+/// match its world size; earlier generations may be at another one, and
+/// workers remap those. `None` skips the world size check.
+pub fn verify_step(worker_dir: &Path, step: u64, rank: usize, current: Option<Generation>) -> Result<(), Invalid> {
+    let dir = worker_dir.join(format!("step_{}", step));
+    if !dir.is_dir() {
+        return Err(Invalid::Missing);
+    }
+    let manifest: Option<Value> = std::fs::read_to_string(dir.join("manifest.json"))
+        .ok()
+        .and_then(|c| serde_json::from_str(&c).ok());
+    let committed = manifest.is_some_and(|m| {
+        m.get("committed").and_then(Value::as_bool) == Some(true)
+            && m.get("step").and_then(Value::as_u64).is_none_or(|s| s == step)
+    });
+    if !committed {
+        return Err(Invalid::NotCommitted);
+    }
+    let content = std::fs::read_to_string(dir.join("state.json")).map_err(|e| Invalid::BadState(e.to_string()))?;
+    let state: Value = serde_json::from_str(&content).map_err(|e| Invalid::BadState(e.to_string()))?;
+    let field = |key: &str| state.get(key).and_then(Value::as_u64);
+    if field("rank").is_some_and(|r| r != rank as u64) {
+        return Err(Invalid::WrongRank(field("rank")));
+    }
+    if let Some(current) = current.filter(|g| field("generation").unwrap_or_default() == g.number) {
+        if field("world_size").is_some_and(|n| n != current.world_size as u64) {
+            return Err(Invalid::WrongWorldSize(field("world_size")));
+        }
+    }
+    Ok(())
+}
+
+/// How [`repair_latest`] moved a rank's `LATEST` pointer.
+#[derive(Debug, Clone)]
+pub struct Repair {
+    /// What `LATEST` said before.
+    pub from: String,
+    /// The step it points to now; `None` if no step was valid and the
+    /// pointer was removed, so the rank starts over.
+    pub to: Option<u64>,
+    pub reason: String,
+}
+
+/// Verifies the checkpoint the rank's `LATEST` points to. If it cannot be
+/// resumed from, `LATEST` is rolled back to the newest valid step.
+pub fn repair_latest(worker_dir: &Path, rank: usize, generation: Generation) -> Result<Option<Repair>> {
+    let path = worker_dir.join("LATEST");
+    let latest = match std::fs::read_to_string(&path) {
+        Ok(latest) => latest.trim().to_string(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+    };
+    let reason = match parse_step_dir(&latest) {
+        None => format!("LATEST names {:?}, not a step directory", latest),
+        Some(step) => match verify_step(worker_dir, step, rank, Some(generation)) {
+            Ok(()) => return Ok(None),
+            Err(invalid) => format!("{} is {}", latest, invalid),
+        },
+    };
+
+    let to = list_steps(worker_dir)
+        .into_iter()
+        .rev()
+        .find(|&s| verify_step(worker_dir, s, rank, Some(generation)).is_ok());
+    match to {
+        Some(step) => write_atomic(&path, format!("step_{}", step).as_bytes())?,
+        None => std::fs::remove_file(&path).with_context(|| format!("failed to remove {}", path.display()))?,
+    }
+    Ok(Some(Repair { from: latest, to, reason }))
+}
+
+/// The parts of a step's `manifest.json` that `coordinator ckpt` shows.
+#[derive(Debug, Default, Deserialize)]
+struct StepManifest {
+    timestamp: Option<f64>,
+    committed: Option<bool>,
+}
+
+/// Steps per second between the two newest committed checkpoints of a
+/// rank, from the timestamps in their `manifest.json`.
+pub fn checkpoint_rate(worker_dir: &Path) -> Option<f64> {
+    let mut committed = list_steps(worker_dir).into_iter().rev().filter_map(|step| {
+        let manifest = read_manifest(&worker_dir.join(format!("step_{}", step)))?;
+        (manifest.committed == Some(true)).then_some((step, manifest.timestamp?))
+    });
+    let (newest, newest_at) = committed.next()?;
+    let (older, older_at) = committed.next()?;
+    let span = newest_at - older_at;
+    (span > 0.0).then(|| (newest - older) as f64 / span)
+}
+
+fn read_manifest(step_dir: &Path) -> Option<StepManifest> {
+    let content = std::fs::read_to_string(step_dir.join("manifest.json")).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+fn read_state(worker_dir: &Path, step: u64) -> Result<Value> {
+    let path = worker_dir.join(format!("step_{}", step)).join("state.json");
+    let content = std::fs::read_to_string(&path).with_context(|| format!("failed to read {}", path.display()))?;
+    serde_json::from_str(&content).with_context(|| format!("invalid JSON in {}", path.display()))
+}
+
+fn format_time(ts: f64) -> String {
+    chrono::DateTime::from_timestamp_millis((ts * 1000.0) as i64)
+        .map_or_else(|| ts.to_string(), |t| t.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string())
+}
+
+/// A job's worker directories, or just those of `ranks` if given.
+fn job_ranks(checkpoint_dir: &Path, job_id: &str, ranks: &[usize]) -> Result<BTreeMap<usize, PathBuf>> {
+    let job_dir = checkpoint_dir.join(job_id);
+    let mut dirs = worker_dirs(&job_dir).with_context(|| format!("failed to read {}", job_dir.display()))?;
+    if dirs.is_empty() {
+        bail!("no checkpoints for job {} in {}", job_id, checkpoint_dir.display());
+    }
+    if !ranks.is_empty() {
+        if let Some(rank) = ranks.iter().find(|r| !dirs.contains_key(r)) {
+            bail!("job {} has no checkpoints for rank {}", job_id, rank);
+        }
+        dirs.retain(|rank, _| ranks.contains(rank));
+    }
+    Ok(dirs)
+}
+
+/// `coordinator ckpt ls`: every step of each rank with its manifest
+/// timestamp and committed flag, marking the `LATEST` and global ones.
+pub fn print_list(checkpoint_dir: &Path, job_id: &str, ranks: &[usize]) -> Result<()> {
+    let global = barrier::read_global_latest(checkpoint_dir, job_id)?;
+    for (rank, dir) in job_ranks(checkpoint_dir, job_id, ranks)? {
+        let latest = std::fs::read_to_string(dir.join("LATEST")).ok();
+        let global_step = global.as_ref().and_then(|g| g.steps.get(&rank).copied());
+        println!("rank {}  LATEST={}", rank, latest.as_deref().map_or("-", str::trim));
+        println!("  {:>8}  {:<9}  {:<24}  MARK", "STEP", "COMMITTED", "WRITTEN");
+        for step in list_steps(&dir) {
+            let manifest = read_manifest(&dir.join(format!("step_{}", step))).unwrap_or_default();
+            let mut marks = Vec::new();
+            if latest.as_deref().map(str::trim) == Some(format!("step_{}", step).as_str()) {
+                marks.push("latest");
+            }
+            if global_step == Some(step) {
+                marks.push("global");
+            }
+            println!(
+                "  {:>8}  {:<9}  {:<24}  {}",
+                step,
+                manifest.committed.map_or("-", |c| if c { "yes" } else { "no" }),
+                manifest.timestamp.map_or("-".to_string(), format_time),
+                marks.join(",")
+            );
+        }
+    }
+    Ok(())
+}
+
+/// `coordinator ckpt show`: pretty-prints a step's `state.json`, by default
+/// the one `LATEST` points to.
+pub fn print_state(checkpoint_dir: &Path, job_id: &str, rank: usize, step: Option<u64>) -> Result<()> {
+    let dir = job_ranks(checkpoint_dir, job_id, &[rank])?.remove(&rank).unwrap_or_default();
+    let step = match step {
+        Some(step) => step,
+        None => latest_step(&dir).ok_or_else(|| anyhow!("rank {} has no LATEST checkpoint", rank))?,
+    };
+    let state = read_state(&dir, step)?;
+    println!("{}", serde_json::to_string_pretty(&state)?);
+    Ok(())
+}
+
+/// `coordinator ckpt diff`: the top-level `state.json` fields that differ
+/// between two steps of a rank. Large values are only reported as changed.
+pub fn print_diff(checkpoint_dir: &Path, job_id: &str, rank: usize, from: u64, to: u64) -> Result<()> {
+    let dir = job_ranks(checkpoint_dir, job_id, &[rank])?.remove(&rank).unwrap_or_default();
+    let (a, b) = (read_state(&dir, from)?, read_state(&dir, to)?);
+    for (sign, step) in [("---", from), ("+++", to)] {
+        let written = read_manifest(&dir.join(format!("step_{}", step)))
+            .and_then(|m| m.timestamp)
+            .map_or("-".to_string(), format_time);
+        println!("{} rank {} step_{} ({})", sign, rank, step, written);
+    }
+    let (Value::Object(a), Value::Object(b)) = (a, b) else {
+        bail!("state.json of rank {} is not a JSON object", rank);
+    };
+    let keys: BTreeSet<&String> = a.keys().chain(b.keys()).collect();
+    let show = |v: Option<&Value>| v.map_or("(absent)".to_string(), |v| v.to_string());
+    let mut changed = 0;
+    for key in keys {
+        let (old, new) = (a.get(key), b.get(key));
+        if old == new {
+            continue;
+        }
+        changed += 1;
+        let (old, new) = (show(old), show(new));
+        if old.len() > 60 || new.len() > 60 {
+            println!("  {}: changed ({} -> {} bytes)", key, old.len(), new.len());
+        } else {
+            println!("  {}: {} -> {}", key, old, new);
+        }
+    }
+    if changed == 0 {
+        println!("  (identical)");
+    }
+    Ok(())
+}
+
+/// Where `coordinator ckpt rollback` moves `LATEST` pointers.
+#[derive(Debug, Clone, Copy)]
+pub enum RollbackTarget {
+    Step(u64),
+    /// Each rank's step in `GLOBAL_LATEST`.
+    Global,
+}
+
+/// `checkpoint_dir/<job>/ROLLBACK`: the pointers a rollback is moving.
+fn rollback_path(checkpoint_dir: &Path, job_id: &str) -> PathBuf {
+    checkpoint_dir.join(job_id).join("ROLLBACK")
+}
+
+/// A rollback, written down before any pointer moves so that one cut
+/// short can be finished instead of leaving the ranks at mixed steps.
+#[derive(Debug, Serialize, Deserialize)]
+struct RollbackIntent {
+    /// New `LATEST` step of each rank.
+    steps: BTreeMap<usize, u64>,
+    /// Whether `GLOBAL_LATEST` is removed.
+    remove_global: bool,
+}
+
+impl RollbackIntent {
+    /// Moves the pointers and removes the intent. Safe to repeat. Returns
+    /// one line per moved pointer.
+    fn apply(&self, checkpoint_dir: &Path, job_id: &str) -> Result<Vec<String>> {
+        let mut done = Vec::new();
+        if self.remove_global {
+            let path = barrier::global_latest_path(checkpoint_dir, job_id);
+            match std::fs::remove_file(&path) {
+                Ok(()) => done.push("removed the global checkpoint (ahead of the new LATEST)".to_string()),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e).with_context(|| format!("failed to remove {}", path.display())),
+            }
+        }
+        for (&rank, step) in &self.steps {
+            let dir = worker_dir(checkpoint_dir, job_id, rank);
+            let previous = std::fs::read_to_string(dir.join("LATEST")).unwrap_or_default();
+            write_atomic(&dir.join("LATEST"), format!("step_{}", step).as_bytes())?;
+            let previous = previous.trim();
+            done.push(format!(
+                "rank {}: LATEST {} -> step_{}",
+                rank,
+                if previous.is_empty() { "-" } else { previous },
+                step
+            ));
+        }
+        let path = rollback_path(checkpoint_dir, job_id);
+        std::fs::remove_file(&path).with_context(|| format!("failed to remove {}", path.display()))?;
+        Ok(done)
+    }
+}
+
+/// Finishes a rollback that was cut short, if the job has one. The caller
+/// holds the job's lock.
+pub fn finish_rollback(checkpoint_dir: &Path, job_id: &str) -> Result<Vec<String>> {
+    let path = rollback_path(checkpoint_dir, job_id);
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+    };
+    let intent: RollbackIntent =
+        serde_json::from_str(&content).with_context(|| format!("invalid rollback in {}", path.display()))?;
+    intent.apply(checkpoint_dir, job_id)
+}
+
+/// `coordinator ckpt rollback`: points `LATEST` of `ranks` (all of the
+/// job's if empty) at the target step. Refused while the job's coordinator
+/// runs. Every target is verified and the plan written to `ROLLBACK`
+/// before any pointer is moved; a rollback cut short is finished by the
+/// next rollback or coordinator start. A `GLOBAL_LATEST` ahead of the new
+/// pointers would send the ranks forward again through `RESUME_STEP` on
+/// their next start, so it is removed.
+pub fn rollback(checkpoint_dir: &Path, job_id: &str, ranks: &[usize], target: RollbackTarget) -> Result<()> {
+    let dirs = job_ranks(checkpoint_dir, job_id, ranks)?;
+    let Some(_lock) = state::try_lock(checkpoint_dir, job_id)? else {
+        bail!("job {} has a running coordinator; stop it before rolling back", job_id);
+    };
+    for line in finish_rollback(checkpoint_dir, job_id)? {
+        println!("finished an interrupted rollback: {}", line);
+    }
+    let saved = state::load(&state::state_path(checkpoint_dir, job_id))?;
+    let current = saved.as_ref().map(|s| Generation { number: s.generation, world_size: s.world_size });
+    let global = barrier::read_global_latest(checkpoint_dir, job_id)?;
+
+    let mut steps = BTreeMap::new();
+    for (rank, dir) in dirs {
+        let step = match target {
+            RollbackTarget::Step(step) => step,
+            RollbackTarget::Global => global
+                .as_ref()
+                .and_then(|g| g.steps.get(&rank).copied())
+                .ok_or_else(|| anyhow!("no global checkpoint lists rank {}", rank))?,
+        };
+        verify_step(&dir, step, rank, current)
+            .map_err(|invalid| anyhow!("rank {}: step_{} is {}; nothing rolled back", rank, step, invalid))?;
+        steps.insert(rank, step);
+    }
+
+    let remove_global = matches!(target, RollbackTarget::Step(_))
+        && global
+            .as_ref()
+            .is_some_and(|g| steps.iter().any(|(rank, step)| g.steps.get(rank).is_some_and(|s| s > step)));
+    let intent = RollbackIntent { steps, remove_global };
+    write_atomic(&rollback_path(checkpoint_dir, job_id), &serde_json::to_vec_pretty(&intent)?)?;
+    for line in intent.apply(checkpoint_dir, job_id)? {
+        println!("{}", line);
+    }
+
+    let running: Vec<usize> = saved
+        .iter()
+        .flat_map(|s| &s.workers)
+        .filter(|r| r.pid.is_some_and(|pid| state::is_our_worker(pid, job_id, r.rank)))
+        .map(|r| r.rank)
+        .collect();
+    if !running.is_empty() {
+        eprintln!(
+            "warning: ranks {:?} were left running by a previous coordinator and will move LATEST again at their next checkpoint",
+            running
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn write_step(dir: &Path, step: u64, manifest: Value, state: &str) {
+        let dir = dir.join(format!("step_{}", step));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("manifest.json"), manifest.to_string()).unwrap();
+        std::fs::write(dir.join("state.json"), state).unwrap();
+    }
+
+    fn committed(step: u64) -> Value {
+        json!({"step": step, "committed": true})
+    }
+
+    fn state(rank: u64, world_size: u64, generation: u64) -> String {
+        json!({"rank": rank, "world_size": world_size, "generation": generation, "step": 10}).to_string()
+    }
+
+    const GEN0: Option<Generation> = Some(Generation { number: 0, world_size: 2 });
+
+    #[test]
+    fn committed_step_of_the_rank_is_valid() {
+        let dir = tempfile::tempdir().unwrap();
+        write_step(dir.path(), 10, committed(10), &state(1, 2, 0));
+        assert_eq!(verify_step(dir.path(), 10, 1, GEN0), Ok(()));
+    }
+
+    #[test]
+    fn missing_or_uncommitted_steps_are_invalid() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(verify_step(dir.path(), 10, 0, GEN0), Err(Invalid::Missing));
+
+        write_step(dir.path(), 10, json!({"step": 10, "committed": false}), &state(0, 2, 0));
+        assert_eq!(verify_step(dir.path(), 10, 0, GEN0), Err(Invalid::NotCommitted));
+        // A manifest copied from another step does not count.
+        write_step(dir.path(), 20, committed(10), &state(0, 2, 0));
+        assert_eq!(verify_step(dir.path(), 20, 0, GEN0), Err(Invalid::NotCommitted));
+    }
+
+    #[test]
+    fn unreadable_state_is_invalid() {
+        let dir = tempfile::tempdir().unwrap();
+        write_step(dir.path(), 10, committed(10), "{\"rank\": 0");
+        assert!(matches!(verify_step(dir.path(), 10, 0, GEN0), Err(Invalid::BadState(_))));
+    }
+
+    #[test]
+    fn state_of_another_rank_is_invalid() {
+        let dir = tempfile::tempdir().unwrap();
+        write_step(dir.path(), 10, committed(10), &state(1, 2, 0));
+        assert_eq!(verify_step(dir.path(), 10, 0, GEN0), Err(Invalid::WrongRank(Some(1))));
+    }
+
+    #[test]
+    fn world_size_must_match_only_in_the_current_generation() {
+        let dir = tempfile::tempdir().unwrap();
+        write_step(dir.path(), 10, committed(10), &state(0, 4, 0));
+        assert_eq!(verify_step(dir.path(), 10, 0, GEN0), Err(Invalid::WrongWorldSize(Some(4))));
+        assert_eq!(verify_step(dir.path(), 10, 0, None), Ok(()));
+        // Workers remap a checkpoint of an earlier generation.
+        let gen1 = Some(Generation { number: 1, world_size: 2 });
+        assert_eq!(verify_step(dir.path(), 10, 0, gen1), Ok(()));
+    }
+}
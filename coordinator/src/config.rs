@@ -0,0 +1,537 @@
+use crate::launcher::WorkerOverride;
+use crate::straggler::StragglerPolicy;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Fully resolved coordinator settings.
+///
+/// Every field can be set, in increasing order of precedence, by the
+/// built-in default, the job file (`--config`), an environment variable named
+/// after the field in upper case with a `COORDINATOR_` prefix (`world_size` ->
+/// `COORDINATOR_WORLD_SIZE`), and a CLI flag. The settings in
+/// `LEGACY_ENV_KEYS` are also read from their unprefixed variable.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub job_id: String,
+    pub world_size: usize,
+    pub checkpoint_dir: String,
+    pub checkpoint_every: usize,
+    pub sleep_sec: f64,
+    pub dataset_dir: String,
+    pub heartbeat_timeout: u64,
+    /// Seconds without step progress before a rank counts as hung; 0 disables.
+    pub progress_timeout: u64,
+    /// Signal sent to a hung rank for a stack dump before killing it; empty disables.
+    pub hang_dump_signal: String,
+    /// Seconds between the stack dump signal and the kill.
+    pub hang_dump_grace: u64,
+    /// A rank whose step rate stays below this fraction of the median rate
+    /// is a straggler; 0 disables straggler detection.
+    pub straggler_threshold: f64,
+    /// Seconds a rank must stay below the threshold before the policy is
+    /// applied, and between applications while it stays there.
+    pub straggler_grace: u64,
+    /// What to do about a straggler: `log`, `restart`, or `reshard` (give
+    /// its leased work to other ranks; needs `shard_leases`).
+    pub straggler_policy: String,
+    /// Address the control API binds to.
+    pub api_bind: String,
+    /// Port of the control API; 0 disables it.
+    pub api_port: u16,
+    /// Port of the scheduler daemon's job submission API, bound on
+    /// `api_bind`.
+    pub scheduler_port: u16,
+    /// Worker processes the scheduler daemon runs at once across all jobs;
+    /// 0 uses the number of CPUs.
+    pub scheduler_slots: usize,
+    /// Let the scheduler daemon stop running jobs of lower priority to
+    /// admit a job that does not fit.
+    pub scheduler_preemption: bool,
+    /// Seconds workers get to checkpoint and exit after SIGTERM on shutdown.
+    pub shutdown_grace: u64,
+    /// Directory for captured worker output (`<log_dir>/<job>/worker_<rank>.log`);
+    /// empty (the default) lets workers inherit the coordinator's stdout/stderr,
+    /// so they can be adopted after a coordinator crash.
+    pub log_dir: String,
+    /// Size at which a worker log is rotated; 0 disables rotation.
+    pub log_max_bytes: u64,
+    /// Rotated worker logs kept per rank.
+    pub log_max_files: usize,
+    /// Also echo captured worker output on the coordinator's stdout.
+    pub log_forward: bool,
+    /// Resume from the state a previous coordinator of this job persisted.
+    pub resume: bool,
+    /// What to do with workers a previous coordinator left running: `adopt` or `kill`.
+    pub orphan_policy: String,
+    /// Assign local dataset shards to ranks through `<job>/shards.json`
+    /// instead of letting each worker compute its own share.
+    pub shard_manifest: bool,
+    /// Hand out shards from a work-stealing lease queue served next to the
+    /// HTTP heartbeat listener, instead of a fixed per-rank assignment.
+    pub shard_leases: bool,
+    /// Seconds a lease survives without its holder reporting training
+    /// progress on it.
+    pub lease_timeout: u64,
+    /// Lines per leased work item; 0 leases whole shards.
+    pub lease_lines: u64,
+    /// Passes over the dataset when shards are leased.
+    pub epochs: usize,
+    /// Seconds between global checkpoint barriers; 0 takes them only on
+    /// request.
+    pub global_checkpoint_interval: u64,
+    /// Seconds every rank gets to commit its checkpoint for a barrier.
+    pub barrier_timeout: u64,
+    /// Check a rank's `LATEST` checkpoint before launching it and roll the
+    /// pointer back to the newest valid step if it is broken.
+    pub verify_checkpoints: bool,
+    /// Seconds between checkpoint garbage collection passes; 0 keeps every
+    /// checkpoint.
+    pub checkpoint_gc_interval: u64,
+    /// Newest checkpoints each rank keeps.
+    pub checkpoint_keep_last: usize,
+    /// Also keep checkpoints whose step is a multiple of this; 0 disables.
+    pub checkpoint_keep_every: u64,
+    /// Seconds between writes of the job's `summary.json`; 0 disables it.
+    pub summary_interval: u64,
+    /// Poll `HEARTBEAT` files under `checkpoint_dir` (shared filesystem mode).
+    pub heartbeat_files: bool,
+    /// Address the heartbeat listeners bind to.
+    pub heartbeat_bind: String,
+    /// Port of the HTTP heartbeat listener; 0 disables it.
+    pub heartbeat_http_port: u16,
+    /// Port of the UDP heartbeat listener; 0 disables it.
+    pub heartbeat_udp_port: u16,
+    /// Host workers use to reach the heartbeat listeners.
+    pub heartbeat_advertise_host: String,
+    /// Lifetime restart budget per rank.
+    pub max_restarts: usize,
+    /// First restart delay (milliseconds).
+    pub restart_backoff_initial_ms: u64,
+    /// Upper bound for the restart delay (milliseconds).
+    pub restart_backoff_max_ms: u64,
+    /// Growth factor of the delay per consecutive failure.
+    pub restart_backoff_multiplier: f64,
+    /// Random spread applied to each delay, as a fraction of it.
+    pub restart_backoff_jitter: f64,
+    /// Uptime (seconds) after which a rank's backoff starts over.
+    pub restart_reset_after: u64,
+    /// Crash exits within `crash_loop_window` that make a rank give up; 0
+    /// disables the crash-loop breaker, leaving only `max_restarts`.
+    pub crash_loop_max_failures: usize,
+    /// Sliding window (seconds) for crash-loop detection.
+    pub crash_loop_window: u64,
+    pub use_s3: bool,
+    /// Worker executable.
+    pub worker_program: String,
+    /// Worker arguments; may contain placeholders such as `{rank}`.
+    pub worker_args: Vec<String>,
+    /// Working directory of worker processes.
+    pub worker_cwd: String,
+    /// Extra env vars for every worker, on top of the standard ones.
+    pub worker_env: BTreeMap<String, String>,
+    /// Per-rank command replacements.
+    pub worker_overrides: Vec<WorkerOverride>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            job_id: "demo-job".to_string(),
+            world_size: 4,
+            checkpoint_dir: "./checkpoints".to_string(),
+            checkpoint_every: 5,
+            sleep_sec: 0.5,
+            dataset_dir: "./data/shards".to_string(),
+            heartbeat_timeout: 10,
+            progress_timeout: 0,
+            hang_dump_signal: String::new(),
+            hang_dump_grace: 5,
+            straggler_threshold: 0.0,
+            straggler_grace: 120,
+            straggler_policy: "log".to_string(),
+            api_bind: "0.0.0.0".to_string(),
+            api_port: 0,
+            scheduler_port: 7070,
+            scheduler_slots: 0,
+            scheduler_preemption: true,
+            shutdown_grace: 20,
+            log_dir: String::new(),
+            log_max_bytes: 10 * 1024 * 1024,
+            log_max_files: 5,
+            log_forward: true,
+            resume: true,
+            orphan_policy: "adopt".to_string(),
+            shard_manifest: true,
+            shard_leases: false,
+            lease_timeout: 30,
+            lease_lines: 0,
+            epochs: 1,
+            global_checkpoint_interval: 0,
+            barrier_timeout: 60,
+            verify_checkpoints: true,
+            checkpoint_gc_interval: 0,
+            checkpoint_keep_last: 3,
+            checkpoint_keep_every: 0,
+            summary_interval: 10,
+            heartbeat_files: true,
+            heartbeat_bind: "0.0.0.0".to_string(),
+            heartbeat_http_port: 0,
+            heartbeat_udp_port: 0,
+            heartbeat_advertise_host: "127.0.0.1".to_string(),
+            max_restarts: 50,
+            restart_backoff_initial_ms: 500,
+            restart_backoff_max_ms: 30_000,
+            restart_backoff_multiplier: 2.0,
+            restart_backoff_jitter: 0.2,
+            restart_reset_after: 60,
+            crash_loop_max_failures: 0,
+            crash_loop_window: 60,
+            use_s3: false,
+            worker_program: "python".to_string(),
+            worker_args: vec!["demo/worker.py".to_string()],
+            worker_cwd: ".".to_string(),
+            worker_env: BTreeMap::new(),
+            worker_overrides: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    fn validate(&self) -> Result<()> {
+        if self.world_size == 0 {
+            bail!("world_size must be at least 1");
+        }
+        if self.checkpoint_every == 0 {
+            bail!("checkpoint_every must be at least 1");
+        }
+        if self.heartbeat_timeout == 0 {
+            bail!("heartbeat_timeout must be at least 1 second");
+        }
+        if !self.heartbeat_files && self.heartbeat_http_port == 0 && self.heartbeat_udp_port == 0 {
+            bail!("no heartbeat source: enable heartbeat_files or set a heartbeat listener port");
+        }
+        if !self.hang_dump_signal.is_empty() {
+            crate::signals::parse_signal(&self.hang_dump_signal)?;
+        }
+        if !matches!(self.orphan_policy.as_str(), "adopt" | "kill") {
+            bail!("orphan_policy must be \"adopt\" or \"kill\", got {:?}", self.orphan_policy);
+        }
+        if !(0.0..1.0).contains(&self.straggler_threshold) {
+            bail!("straggler_threshold must be at least 0.0 and below 1.0");
+        }
+        if self.straggler_threshold > 0.0 {
+            if self.straggler_grace == 0 {
+                bail!("straggler_grace must be at least 1 second");
+            }
+            if StragglerPolicy::parse(&self.straggler_policy).is_none() {
+                bail!(
+                    "straggler_policy must be \"log\", \"restart\" or \"reshard\", got {:?}",
+                    self.straggler_policy
+                );
+            }
+            if self.straggler_policy == "reshard" && !self.shard_leases {
+                bail!("straggler_policy \"reshard\" needs shard_leases");
+            }
+        }
+        if self.api_port != 0 && self.api_port == self.heartbeat_http_port {
+            bail!("api_port and heartbeat_http_port must differ");
+        }
+        if self.shard_leases {
+            if self.heartbeat_http_port == 0 {
+                bail!("shard_leases needs heartbeat_http_port, which serves the leases");
+            }
+            if self.use_s3 || !self.shard_manifest {
+                bail!("shard_leases needs shard_manifest and a local dataset (use_s3 = false)");
+            }
+            if self.lease_timeout == 0 {
+                bail!("lease_timeout must be at least 1 second");
+            }
+            if self.epochs == 0 {
+                bail!("epochs must be at least 1");
+            }
+        }
+        if self.barrier_timeout == 0 {
+            bail!("barrier_timeout must be at least 1 second");
+        }
+        if self.checkpoint_gc_interval > 0 && self.checkpoint_keep_last == 0 {
+            bail!("checkpoint_keep_last must be at least 1 when checkpoint GC is enabled");
+        }
+        if self.restart_backoff_multiplier < 1.0 {
+            bail!("restart_backoff_multiplier must be at least 1.0");
+        }
+        if !(0.0..=1.0).contains(&self.restart_backoff_jitter) {
+            bail!("restart_backoff_jitter must be between 0.0 and 1.0");
+        }
+        if self.crash_loop_max_failures > 0 && self.crash_loop_window == 0 {
+            bail!("crash_loop_window must be at least 1 second");
+        }
+        if self.worker_program.is_empty() {
+            bail!("worker_program must not be empty");
+        }
+        for o in &self.worker_overrides {
+            if o.ranks.is_empty() {
+                bail!("worker_overrides entries must list at least one rank");
+            }
+            if let Some(rank) = o.ranks.iter().find(|r| **r >= self.world_size) {
+                bail!("worker_overrides rank {} is outside world_size {}", rank, self.world_size);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Where a resolved value came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    Default,
+    File(PathBuf),
+    Env(String),
+    Cli,
+    /// Sent with a job submitted to the scheduler daemon.
+    Submission,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => f.write_str("default"),
+            Source::File(path) => write!(f, "file ({})", path.display()),
+            Source::Env(var) => write!(f, "env ({})", var),
+            Source::Cli => f.write_str("cli"),
+            Source::Submission => f.write_str("submission"),
+        }
+    }
+}
+
+/// The resolved config together with the provenance of every key.
+#[derive(Debug, Clone)]
+pub struct ResolvedConfig {
+    pub config: Config,
+    pub sources: BTreeMap<String, Source>,
+}
+
+impl ResolvedConfig {
+    /// Layers defaults, the job file, the environment, and CLI overrides.
+    ///
+    /// `cli` is the serialized CLI override struct; `null` entries are flags
+    /// that were not given.
+    pub fn load(file: Option<&Path>, cli: Value) -> Result<Self> {
+        Self::load_submission(file, cli, Map::new())
+    }
+
+    /// Like [`Self::load`], with the settings of a job submitted to the
+    /// scheduler daemon layered on top.
+    pub fn load_submission(file: Option<&Path>, cli: Value, submitted: Map<String, Value>) -> Result<Self> {
+        let mut merged = match serde_json::to_value(Config::default())? {
+            Value::Object(map) => map,
+            _ => unreachable!("Config serializes to a map"),
+        };
+        let mut sources: BTreeMap<String, Source> =
+            merged.keys().map(|k| (k.clone(), Source::Default)).collect();
+
+        if let Some(path) = file {
+            for (key, value) in read_file(path)? {
+                if !merged.contains_key(&key) {
+                    bail!("unknown key `{}` in {}", key, path.display());
+                }
+                if value.is_null() {
+                    continue;
+                }
+                merged.insert(key.clone(), value);
+                sources.insert(key, Source::File(path.to_path_buf()));
+            }
+        }
+
+        let keys: Vec<String> = merged.keys().cloned().collect();
+        for key in keys {
+            let Some((var, raw)) = env_value(&key, |var| std::env::var(var).ok()) else {
+                continue;
+            };
+            let value = parse_env(&merged[&key], &raw)
+                .with_context(|| format!("invalid value {:?} for {}", raw, var))?;
+            merged.insert(key.clone(), value);
+            sources.insert(key, Source::Env(var));
+        }
+
+        if let Value::Object(overrides) = cli {
+            for (key, value) in overrides {
+                if value.is_null() || !merged.contains_key(&key) {
+                    continue;
+                }
+                merged.insert(key.clone(), value);
+                sources.insert(key, Source::Cli);
+            }
+        }
+
+        for (key, value) in submitted {
+            if !merged.contains_key(&key) {
+                bail!("unknown setting `{}`", key);
+            }
+            if value.is_null() {
+                continue;
+            }
+            merged.insert(key.clone(), value);
+            sources.insert(key, Source::Submission);
+        }
+
+        let config: Config =
+            serde_json::from_value(Value::Object(merged)).context("invalid configuration")?;
+        config.validate()?;
+        Ok(Self { config, sources })
+    }
+
+    /// Renders the resolved config as TOML, annotating each key with its source.
+    pub fn render(&self) -> Result<String> {
+        let table = match toml::Value::try_from(&self.config)? {
+            toml::Value::Table(table) => table,
+            _ => unreachable!("Config serializes to a table"),
+        };
+        let mut out = String::new();
+        for (key, value) in table {
+            let source = self.sources.get(&key).unwrap_or(&Source::Default);
+            out.push_str(&format!("{} = {}  # {}\n", key, value, source));
+        }
+        Ok(out)
+    }
+}
+
+/// Reads one setting from a job file again, for settings that can change
+/// while the job runs.
+pub fn read_key(path: &Path, key: &str) -> Result<Option<Value>> {
+    Ok(read_file(path)?.remove(key).filter(|v| !v.is_null()))
+}
+
+fn read_file(path: &Path) -> Result<Map<String, Value>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read config file {}", path.display()))?;
+    let value: Value = match path.extension().and_then(|e| e.to_str()) {
+        Some("yaml") | Some("yml") => serde_yaml::from_str(&text)
+            .with_context(|| format!("failed to parse {} as YAML", path.display()))?,
+        _ => toml::from_str(&text)
+            .with_context(|| format!("failed to parse {} as TOML", path.display()))?,
+    };
+    match value {
+        Value::Object(map) => Ok(map),
+        Value::Null => Ok(Map::new()),
+        _ => bail!("{} must contain a table of settings", path.display()),
+    }
+}
+
+/// Prefix of the environment variable of every setting.
+const ENV_PREFIX: &str = "COORDINATOR_";
+
+/// Settings also read from their bare upper-case name, as deployments of the
+/// original coordinator (and the k8s manifests) set them. Other bare names
+/// are ignored: variables such as `API_PORT` are injected by Kubernetes
+/// service links and have nothing to do with the coordinator.
+const LEGACY_ENV_KEYS: &[&str] = &[
+    "job_id",
+    "world_size",
+    "checkpoint_dir",
+    "checkpoint_every",
+    "sleep_sec",
+    "dataset_dir",
+    "heartbeat_timeout",
+    "max_restarts",
+    "use_s3",
+];
+
+/// The variable `key` is set by and its raw value. The prefixed variable
+/// wins over a legacy one.
+fn env_value(key: &str, lookup: impl Fn(&str) -> Option<String>) -> Option<(String, String)> {
+    let prefixed = format!("{}{}", ENV_PREFIX, key.to_uppercase());
+    if let Some(raw) = lookup(&prefixed) {
+        return Some((prefixed, raw));
+    }
+    if !LEGACY_ENV_KEYS.contains(&key) {
+        return None;
+    }
+    let legacy = key.to_uppercase();
+    lookup(&legacy).map(|raw| (legacy, raw))
+}
+
+/// Parses an environment string into the JSON type of the key's default.
+fn parse_env(default: &Value, raw: &str) -> Result<Value> {
+    let raw = raw.trim();
+    Ok(match default {
+        Value::String(_) => Value::String(raw.to_string()),
+        Value::Bool(_) => match raw.to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => Value::Bool(true),
+            "0" | "false" | "no" | "off" | "" => Value::Bool(false),
+            _ => bail!("expected a boolean"),
+        },
+        Value::Number(n) if n.is_f64() => Value::from(raw.parse::<f64>()?),
+        Value::Number(_) => Value::from(raw.parse::<u64>()?),
+        // Lists accept JSON or a whitespace-separated string.
+        Value::Array(_) if !raw.starts_with('[') => {
+            Value::from(raw.split_whitespace().collect::<Vec<_>>())
+        }
+        _ => serde_json::from_str(raw)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn env_value_prefers_the_prefixed_variable() {
+        let lookup = env(&[("COORDINATOR_WORLD_SIZE", "4"), ("WORLD_SIZE", "2")]);
+        assert_eq!(
+            env_value("world_size", &lookup),
+            Some(("COORDINATOR_WORLD_SIZE".to_string(), "4".to_string()))
+        );
+        let lookup = env(&[("WORLD_SIZE", "2")]);
+        assert_eq!(env_value("world_size", &lookup), Some(("WORLD_SIZE".to_string(), "2".to_string())));
+    }
+
+    #[test]
+    fn env_value_ignores_bare_names_outside_the_legacy_list() {
+        // Kubernetes service links set variables like this one.
+        let lookup = env(&[("API_PORT", "tcp://10.0.0.1:8080")]);
+        assert_eq!(env_value("api_port", &lookup), None);
+        let lookup = env(&[("COORDINATOR_API_PORT", "8080")]);
+        assert_eq!(
+            env_value("api_port", &lookup),
+            Some(("COORDINATOR_API_PORT".to_string(), "8080".to_string()))
+        );
+    }
+
+    #[test]
+    fn parse_env_follows_the_default_type() {
+        assert_eq!(parse_env(&json!("x"), " job-a ").unwrap(), json!("job-a"));
+        assert_eq!(parse_env(&json!(0), "12").unwrap(), json!(12));
+        assert_eq!(parse_env(&json!(0.5), "1.5").unwrap(), json!(1.5));
+        assert_eq!(parse_env(&json!(["a"]), "b c").unwrap(), json!(["b", "c"]));
+        assert_eq!(parse_env(&json!(["a"]), r#"["b c"]"#).unwrap(), json!(["b c"]));
+    }
+
+    #[test]
+    fn parse_env_reads_booleans() {
+        for raw in ["1", "true", "YES", "on"] {
+            assert_eq!(parse_env(&json!(false), raw).unwrap(), json!(true), "{}", raw);
+        }
+        for raw in ["0", "false", "No", "off", ""] {
+            assert_eq!(parse_env(&json!(true), raw).unwrap(), json!(false), "{}", raw);
+        }
+        assert!(parse_env(&json!(false), "maybe").is_err());
+    }
+
+    #[test]
+    fn parse_env_rejects_a_malformed_number() {
+        assert!(parse_env(&json!(0), "-1").is_err());
+        assert!(parse_env(&json!(0), "tcp://10.0.0.1:8080").is_err());
+    }
+}
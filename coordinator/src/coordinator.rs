@@ -0,0 +1,1409 @@
+use crate::api;
+use crate::barrier::{BarrierEvent, BarrierStatus, CheckpointBarrier, GlobalCheckpoint};
+use crate::checkpoint;
+use crate::config::{self, Config, ResolvedConfig, Source};
+use crate::events::{Event, Journal};
+use crate::heartbeat::{self, WorkerHeartbeat};
+use crate::launcher::spawn_worker;
+use crate::leases::{LeaseProgress, LeaseQueue};
+use crate::logs::WorkerLogs;
+use crate::restart::{RestartDecision, RestartPolicy};
+use crate::retention;
+use crate::shards::{self, ShardManifest};
+use crate::shutdown::{self, ShutdownSignals};
+use crate::summary::{self, RateWindow, Summarizer};
+use crate::signals::{parse_signal, process_alive, send_signal};
+use crate::straggler::{Straggler, StragglerDetector, StragglerPolicy};
+use crate::state::{self, JobState, RankState};
+use crate::worker::{ExitInfo, FailureReason, JobOutcome, Worker, WorkerState, WorkerStatus};
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::process::Child;
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio::sync::Notify;
+use tokio::time::sleep;
+use tracing::{error, info, warn};
+
+pub fn now_secs() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+/// State shared between the monitor loop, the restart tasks, and the
+/// network listeners.
+pub struct Shared {
+    pub config: Config,
+    pub config_sources: BTreeMap<String, Source>,
+    pub workers: Mutex<HashMap<usize, Worker>>,
+    /// Set once the job is shutting down; no more restarts happen.
+    pub stopping: AtomicBool,
+    /// Wakes the monitor loop to stop the job.
+    pub stop: Notify,
+    pub started_at: f64,
+    pub journal: Journal,
+    pub logs: WorkerLogs,
+    /// Set when shards are leased instead of assigned up front.
+    pub leases: Option<Mutex<LeaseQueue>>,
+    /// Current world size; changed only by the monitor loop.
+    pub generation: Mutex<Generation>,
+    /// The current shard manifest. Elastic resizing needs one.
+    pub manifest: Mutex<Option<ShardManifest>>,
+    /// World size an operator asked for, picked up by the monitor loop.
+    pub rescale: Mutex<Option<usize>>,
+    /// Set by the scheduler: fails unless it has the given number of free
+    /// worker slots for the job to grow into.
+    pub slot_budget: OnceLock<SlotBudget>,
+    pub rescale_requested: Notify,
+    /// Global checkpoint barrier; advanced by the monitor loop.
+    pub barrier: Mutex<CheckpointBarrier>,
+    /// Bytes deleted by checkpoint garbage collection so far.
+    pub gc_reclaimed_bytes: AtomicU64,
+    /// Recent steps of each rank; sampled by the monitor loop.
+    pub step_rates: Mutex<HashMap<usize, RateWindow>>,
+    pub summarizer: Mutex<Summarizer>,
+}
+
+pub type SlotBudget = Box<dyn Fn(usize) -> Result<(), String> + Send + Sync>;
+
+/// The job's world size and how many times it has changed. Ranks record
+/// the generation they were launched in with their checkpoints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Generation {
+    pub number: u64,
+    pub world_size: usize,
+}
+
+/// Serializable snapshot of the whole job.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobStatus {
+    pub job_id: String,
+    pub world_size: usize,
+    pub generation: u64,
+    pub uptime_secs: f64,
+    pub stopping: bool,
+    pub workers: Vec<WorkerStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub leases: Option<LeaseProgress>,
+    /// Last complete global checkpoint.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub global_checkpoint: Option<GlobalCheckpoint>,
+    /// Global checkpoint barrier in progress.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checkpoint_barrier: Option<BarrierStatus>,
+}
+
+/// Why an operator action could not be applied.
+#[derive(Debug)]
+pub enum ControlError {
+    UnknownRank(usize),
+    /// The action does not apply in the rank's or job's current state.
+    Conflict(String),
+    Spawn(anyhow::Error),
+}
+
+impl fmt::Display for ControlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ControlError::UnknownRank(rank) => write!(f, "unknown rank {}", rank),
+            ControlError::Conflict(msg) => f.write_str(msg),
+            ControlError::Spawn(e) => write!(f, "{:#}", e),
+        }
+    }
+}
+
+impl Shared {
+    pub fn workers(&self) -> MutexGuard<'_, HashMap<usize, Worker>> {
+        self.workers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn status(&self) -> JobStatus {
+        let now = now_secs();
+        let generation = self.generation();
+        let (global_checkpoint, checkpoint_barrier) = {
+            let barrier = self.barrier();
+            (barrier.latest().cloned(), barrier.status())
+        };
+        let workers = self.workers();
+        let mut statuses: Vec<WorkerStatus> = workers.values().map(|w| w.status(now)).collect();
+        statuses.sort_by_key(|w| w.rank);
+        JobStatus {
+            job_id: self.config.job_id.clone(),
+            world_size: generation.world_size,
+            generation: generation.number,
+            uptime_secs: now - self.started_at,
+            stopping: self.stopping.load(Ordering::SeqCst),
+            workers: statuses,
+            leases: self.lease_progress(),
+            global_checkpoint,
+            checkpoint_barrier,
+        }
+    }
+
+    pub fn generation(&self) -> Generation {
+        *self.generation.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn barrier(&self) -> MutexGuard<'_, CheckpointBarrier> {
+        self.barrier.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn step_rates(&self) -> MutexGuard<'_, HashMap<usize, RateWindow>> {
+        self.step_rates.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn summarizer(&self) -> MutexGuard<'_, Summarizer> {
+        self.summarizer.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn lease_progress(&self) -> Option<LeaseProgress> {
+        let leases = self.leases.as_ref()?;
+        Some(leases.lock().unwrap_or_else(|e| e.into_inner()).progress())
+    }
+
+    /// Launches a process for `rank` and hooks up its log capture. The rank
+    /// resumes from the last global checkpoint if there is one.
+    pub fn spawn(&self, rank: usize, attempt: usize) -> Result<Child> {
+        let generation = self.generation();
+        let mut resume_step = self.barrier().resume_step(generation, rank);
+        if self.config.verify_checkpoints {
+            resume_step = self.verify_checkpoint(rank, generation, resume_step);
+        }
+        let mut child = spawn_worker(&self.config, generation, rank, resume_step)?;
+        if let Err(e) = self.logs.attach(&mut child, rank, attempt) {
+            let _ = child.start_kill();
+            return Err(e);
+        }
+        Ok(child)
+    }
+
+    /// [`Shared::spawn`] on the blocking pool, so verifying checkpoints and
+    /// forking the process do not hold up the runtime.
+    pub async fn spawn_off_runtime(self: &Arc<Self>, rank: usize, attempt: usize) -> Result<Child> {
+        let shared = self.clone();
+        tokio::task::spawn_blocking(move || shared.spawn(rank, attempt)).await?
+    }
+
+    /// Makes sure the rank does not start from a broken checkpoint: repairs
+    /// its `LATEST` pointer if needed and drops a global checkpoint step
+    /// that is no longer valid. Returns the step to resume from.
+    fn verify_checkpoint(&self, rank: usize, generation: Generation, resume_step: Option<u64>) -> Option<u64> {
+        let dir = checkpoint::worker_dir(Path::new(&self.config.checkpoint_dir), &self.config.job_id, rank);
+        match checkpoint::repair_latest(&dir, rank, generation) {
+            Ok(None) => {}
+            Ok(Some(repair)) => {
+                match repair.to {
+                    Some(step) => warn!(
+                        "[coord] rank={} {}; rolled LATEST back to step_{}",
+                        rank, repair.reason, step
+                    ),
+                    None => warn!(
+                        "[coord] rank={} {}; no valid checkpoint left, starting over",
+                        rank, repair.reason
+                    ),
+                }
+                self.journal.record(Event::CheckpointRepaired {
+                    rank,
+                    from: repair.from,
+                    to: repair.to,
+                    reason: repair.reason,
+                });
+            }
+            Err(e) => warn!("[coord] failed to verify checkpoints of rank={}: {:#}", rank, e),
+        }
+        let step = resume_step?;
+        match checkpoint::verify_step(&dir, step, rank, Some(generation)) {
+            Ok(()) => Some(step),
+            Err(invalid) => {
+                warn!(
+                    "[coord] rank={} global checkpoint step_{} is {}; resuming from LATEST instead",
+                    rank, step, invalid
+                );
+                None
+            }
+        }
+    }
+
+    /// Replaces the rank's process right away, bypassing the restart policy.
+    /// Also revives ranks that already finished or were given up on.
+    /// `reason` labels the restart in the metrics.
+    pub async fn restart_rank(self: &Arc<Self>, rank: usize, reason: &str) -> Result<(), ControlError> {
+        if self.stopping.load(Ordering::SeqCst) {
+            return Err(ControlError::Conflict("job is stopping".to_string()));
+        }
+        let attempt = {
+            let mut workers = self.workers();
+            let w = workers.get_mut(&rank).ok_or(ControlError::UnknownRank(rank))?;
+            if w.state == WorkerState::Pending {
+                return Err(ControlError::Conflict(format!("rank {} is already being restarted", rank)));
+            }
+            if let Some(task) = w.restart_task.take() {
+                task.abort();
+            }
+            w.restart_at = None;
+            w.drain_deadline = None;
+            w.kill();
+            w.state = WorkerState::Pending;
+            w.record_restart(reason);
+            w.restarts
+        };
+        info!("[coord] restarting rank={} ({})", rank, reason);
+        self.journal.record(Event::RestartRequested { rank });
+        let spawned = self.spawn_off_runtime(rank, attempt).await;
+        self.finish_spawn(rank, spawned)
+    }
+
+    /// Second half of a restart, for a rank marked `Pending` before its
+    /// process was spawned outside the lock: installs the process, or marks
+    /// the rank `Failed` if the spawn failed. If the rank was stopped in the
+    /// meantime it stays that way and the new process is killed.
+    fn finish_spawn(&self, rank: usize, spawned: Result<Child>) -> Result<(), ControlError> {
+        let mut workers = self.workers();
+        let Some(w) = workers.get_mut(&rank).filter(|w| w.state == WorkerState::Pending) else {
+            if let Ok(mut child) = spawned {
+                let _ = child.start_kill();
+            }
+            return Err(ControlError::Conflict(format!("rank {} was stopped while restarting", rank)));
+        };
+        match spawned {
+            Ok(child) => {
+                w.start(child);
+                self.journal.record(Event::WorkerSpawned { rank, pid: w.pid(), attempt: w.restarts });
+                Ok(())
+            }
+            Err(e) => {
+                error!("[coord] failed to restart rank={}: {:#}", rank, e);
+                w.state = WorkerState::Failed;
+                w.last_failure = Some(FailureReason::SpawnFailed);
+                self.journal.record(Event::SpawnFailed {
+                    rank,
+                    attempt: w.restarts,
+                    error: format!("{:#}", e),
+                });
+                Err(ControlError::Spawn(e))
+            }
+        }
+    }
+
+    /// SIGKILLs the rank's process. A running rank is then handled like any
+    /// other failure and restarted per the restart policy.
+    pub fn kill_rank(&self, rank: usize) -> Result<(), ControlError> {
+        let mut workers = self.workers();
+        let w = workers.get_mut(&rank).ok_or(ControlError::UnknownRank(rank))?;
+        match w.state {
+            WorkerState::Running => {
+                warn!("[coord] killing rank={} on request", rank);
+                self.fail_worker(w, FailureReason::Killed);
+                Ok(())
+            }
+            WorkerState::Draining => {
+                warn!("[coord] killing draining rank={} on request", rank);
+                self.journal.record(Event::WorkerStopped {
+                    rank,
+                    pid: w.pid(),
+                    code: None,
+                    signal: None,
+                    killed: true,
+                });
+                w.kill();
+                w.drain_deadline = None;
+                w.state = WorkerState::Stopped;
+                Ok(())
+            }
+            state => Err(ControlError::Conflict(format!(
+                "rank {} has no running process (state {})",
+                rank, state
+            ))),
+        }
+    }
+
+    /// Asks the rank to checkpoint and exit, without restarting it.
+    pub fn drain_rank(&self, rank: usize) -> Result<(), ControlError> {
+        let mut workers = self.workers();
+        let w = workers.get_mut(&rank).ok_or(ControlError::UnknownRank(rank))?;
+        match w.state {
+            WorkerState::Running => {
+                if let Some(pid) = w.pid() {
+                    if let Err(e) = send_signal(pid, libc::SIGTERM) {
+                        warn!("[coord] failed to send SIGTERM to rank={} pid={}: {}", rank, pid, e);
+                    }
+                }
+                info!("[coord] draining rank={} on request", rank);
+                self.journal.record(Event::DrainRequested { rank, pid: w.pid() });
+                w.state = WorkerState::Draining;
+                w.drain_deadline = Some(now_secs() + self.config.shutdown_grace as f64);
+                Ok(())
+            }
+            WorkerState::Pending | WorkerState::Failed => {
+                if let Some(task) = w.restart_task.take() {
+                    task.abort();
+                }
+                w.restart_at = None;
+                w.state = WorkerState::Stopped;
+                self.journal.record(Event::WorkerStopped {
+                    rank,
+                    pid: None,
+                    code: None,
+                    signal: None,
+                    killed: false,
+                });
+                Ok(())
+            }
+            state => Err(ControlError::Conflict(format!(
+                "rank {} cannot be drained (state {})",
+                rank, state
+            ))),
+        }
+    }
+
+    /// Kills the rank's process, if any, marks it `Failed`, and journals why.
+    pub fn fail_worker(&self, w: &mut Worker, reason: FailureReason) {
+        self.journal.record(Event::failed(w.rank, w.pid(), reason));
+        w.fail(reason);
+    }
+
+    /// Gracefully stops the whole job, as on SIGTERM.
+    pub fn request_stop(&self) {
+        self.stop.notify_one();
+    }
+
+    /// Asks the monitor loop to relaunch the job with `world_size` ranks.
+    pub fn request_rescale(&self, world_size: usize) -> Result<(), ControlError> {
+        if world_size == 0 {
+            return Err(ControlError::Conflict("world_size must be at least 1".to_string()));
+        }
+        if self.stopping.load(Ordering::SeqCst) {
+            return Err(ControlError::Conflict("job is stopping".to_string()));
+        }
+        if self.manifest.lock().unwrap_or_else(|e| e.into_inner()).is_none() {
+            return Err(ControlError::Conflict(
+                "changing the world size needs a shard manifest of a local dataset".to_string(),
+            ));
+        }
+        let growth = world_size.saturating_sub(self.generation().world_size);
+        if let Some(budget) = self.slot_budget.get().filter(|_| growth > 0) {
+            budget(growth).map_err(ControlError::Conflict)?;
+        }
+        info!("[coord] world_size {} requested", world_size);
+        *self.rescale.lock().unwrap_or_else(|e| e.into_inner()) = Some(world_size);
+        self.rescale_requested.notify_one();
+        Ok(())
+    }
+
+    /// Asks the monitor loop to take a global checkpoint as soon as every
+    /// rank is running.
+    pub fn request_checkpoint(&self) -> Result<(), ControlError> {
+        if self.stopping.load(Ordering::SeqCst) {
+            return Err(ControlError::Conflict("job is stopping".to_string()));
+        }
+        self.barrier().request().map_err(ControlError::Conflict)?;
+        info!("[coord] global checkpoint requested");
+        Ok(())
+    }
+}
+
+pub struct Coordinator {
+    job_id: String,
+    checkpoint_dir: PathBuf,
+    max_restarts: usize,
+    heartbeat_timeout: Duration,
+    progress_timeout: Option<Duration>,
+    stragglers: Option<StragglerDetector>,
+    hang_dump_signal: Option<i32>,
+    hang_dump_grace: Duration,
+    shutdown_grace: Duration,
+    restart_policy: RestartPolicy,
+    state_path: PathBuf,
+    /// The job's lock, held while this coordinator exists.
+    _lock: std::fs::File,
+    /// Whether SIGTERM and SIGINT stop the job; see
+    /// [`Self::ignore_shutdown_signals`].
+    handle_signals: bool,
+    /// World size the job was started at, if it differs from the shard
+    /// manifest's.
+    resize_to: Option<usize>,
+    shared: Arc<Shared>,
+}
+
+impl Coordinator {
+    pub fn new(resolved: ResolvedConfig) -> Result<Self> {
+        let ResolvedConfig { config, sources } = resolved;
+        let hang_dump_signal = match config.hang_dump_signal.as_str() {
+            "" => None,
+            name => Some(parse_signal(name)?),
+        };
+        let checkpoint_dir = PathBuf::from(&config.checkpoint_dir);
+        let Some(lock) = state::try_lock(&checkpoint_dir, &config.job_id)? else {
+            bail!("job {} already has a running coordinator", config.job_id);
+        };
+        for line in checkpoint::finish_rollback(&checkpoint_dir, &config.job_id)? {
+            info!("[coord] finished an interrupted checkpoint rollback: {}", line);
+        }
+        let journal = Journal::open(&checkpoint_dir, &config.job_id)?;
+        let barrier = CheckpointBarrier::open(&config, now_secs())?;
+        let state_path = state::state_path(&checkpoint_dir, &config.job_id);
+        let prepared = if config.shard_manifest { shards::prepare(&config)? } else { None };
+        let resize_to = prepared.as_ref().and_then(|p| p.resize_to);
+        let manifest = prepared.map(|p| p.manifest);
+        let generation = match &manifest {
+            Some(m) => Generation { number: m.generation, world_size: m.world_size },
+            None => Generation { number: 0, world_size: config.world_size },
+        };
+        let leases = match (&manifest, config.shard_leases) {
+            (Some(manifest), true) => Some(Mutex::new(LeaseQueue::open(manifest, &config)?)),
+            (None, true) => bail!("shard_leases needs a shard manifest of a local dataset_dir"),
+            (_, false) => None,
+        };
+        Ok(Self {
+            job_id: config.job_id.clone(),
+            checkpoint_dir,
+            max_restarts: config.max_restarts,
+            heartbeat_timeout: Duration::from_secs(config.heartbeat_timeout),
+            progress_timeout: (config.progress_timeout > 0)
+                .then(|| Duration::from_secs(config.progress_timeout)),
+            stragglers: StragglerDetector::from_config(&config),
+            hang_dump_signal,
+            hang_dump_grace: Duration::from_secs(config.hang_dump_grace),
+            shutdown_grace: Duration::from_secs(config.shutdown_grace),
+            restart_policy: RestartPolicy::from_config(&config),
+            state_path,
+            _lock: lock,
+            handle_signals: true,
+            resize_to,
+            shared: Arc::new(Shared {
+                logs: WorkerLogs::from_config(&config),
+                config,
+                config_sources: sources,
+                workers: Mutex::new(HashMap::new()),
+                stopping: AtomicBool::new(false),
+                stop: Notify::new(),
+                started_at: now_secs(),
+                journal,
+                leases,
+                generation: Mutex::new(generation),
+                manifest: Mutex::new(manifest),
+                rescale: Mutex::new(None),
+                slot_budget: OnceLock::new(),
+                rescale_requested: Notify::new(),
+                barrier: Mutex::new(barrier),
+                gc_reclaimed_bytes: AtomicU64::new(0),
+                step_rates: Mutex::new(HashMap::new()),
+                summarizer: Mutex::new(Summarizer::default()),
+            }),
+        })
+    }
+
+    /// Leaves SIGTERM and SIGINT to the caller, which stops the job through
+    /// [`Shared::request_stop`] instead.
+    pub fn ignore_shutdown_signals(&mut self) {
+        self.handle_signals = false;
+    }
+
+    /// State the job's tasks share, for driving the job from outside.
+    pub fn shared(&self) -> Arc<Shared> {
+        self.shared.clone()
+    }
+
+    fn running_ranks(&self) -> Vec<(usize, Option<u32>)> {
+        let workers = self.shared.workers();
+        workers
+            .values()
+            .filter(|w| w.state == WorkerState::Running)
+            .map(|w| (w.rank, w.pid()))
+            .collect()
+    }
+
+    /// Reads the HEARTBEAT file of every running rank, keeping only those
+    /// written by the rank's current process. Done before taking the lock
+    /// for the poll so restart tasks and listeners never wait on file I/O.
+    fn read_heartbeat_files(&self, running: &[(usize, Option<u32>)]) -> HashMap<usize, WorkerHeartbeat> {
+        if !self.shared.config.heartbeat_files {
+            return HashMap::new();
+        }
+        running
+            .iter()
+            .filter_map(|&(rank, pid)| {
+                let dir = checkpoint::worker_dir(&self.checkpoint_dir, &self.job_id, rank);
+                let hb = heartbeat::read_file(&dir)?;
+                (Some(hb.pid) == pid).then_some((rank, hb))
+            })
+            .collect()
+    }
+
+    /// Reads the LATEST checkpoint step of every running rank for the
+    /// progress watchdog and the step rates.
+    fn read_checkpoint_steps(&self, running: &[(usize, Option<u32>)]) -> HashMap<usize, u64> {
+        if self.progress_timeout.is_none() && self.shared.config.summary_interval == 0 && self.stragglers.is_none() {
+            return HashMap::new();
+        }
+        running
+            .iter()
+            .filter_map(|&(rank, _)| {
+                let dir = checkpoint::worker_dir(&self.checkpoint_dir, &self.job_id, rank);
+                Some((rank, checkpoint::latest_step(&dir)?))
+            })
+            .collect()
+    }
+
+    /// Starts the push-based heartbeat listeners that are enabled.
+    fn start_heartbeat_listeners(&self) -> Result<()> {
+        let config = &self.shared.config;
+        let ip: IpAddr = config
+            .heartbeat_bind
+            .parse()
+            .with_context(|| format!("invalid heartbeat_bind {:?}", config.heartbeat_bind))?;
+        if config.heartbeat_http_port != 0 {
+            let addr = SocketAddr::new(ip, config.heartbeat_http_port);
+            let shared = self.shared.clone();
+            tokio::spawn(async move {
+                if let Err(e) = heartbeat::serve_http(shared, addr).await {
+                    error!("[coord] heartbeat listener failed: {:#}", e);
+                }
+            });
+        }
+        if config.heartbeat_udp_port != 0 {
+            let addr = SocketAddr::new(ip, config.heartbeat_udp_port);
+            let shared = self.shared.clone();
+            tokio::spawn(async move {
+                if let Err(e) = heartbeat::serve_udp(shared, addr).await {
+                    error!("[coord] heartbeat listener failed: {:#}", e);
+                }
+            });
+        }
+        Ok(())
+    }
+
+    fn start_api(&self) -> Result<()> {
+        let config = &self.shared.config;
+        if config.api_port == 0 {
+            return Ok(());
+        }
+        let ip: IpAddr = config
+            .api_bind
+            .parse()
+            .with_context(|| format!("invalid api_bind {:?}", config.api_bind))?;
+        let addr = SocketAddr::new(ip, config.api_port);
+        let shared = self.shared.clone();
+        tokio::spawn(async move {
+            if let Err(e) = api::serve(shared, addr).await {
+                error!("[coord] control API failed: {:#}", e);
+            }
+        });
+        Ok(())
+    }
+
+    /// Starts the periodic checkpoint garbage collection, if enabled.
+    fn start_checkpoint_gc(&self) {
+        let interval = self.shared.config.checkpoint_gc_interval;
+        if interval == 0 {
+            return;
+        }
+        let shared = self.shared.clone();
+        tokio::spawn(retention::run(shared, Duration::from_secs(interval)));
+    }
+
+    /// Starts rewriting the job's `summary.json`, if enabled.
+    fn start_summary(&self) {
+        let interval = self.shared.config.summary_interval;
+        if interval == 0 {
+            return;
+        }
+        let shared = self.shared.clone();
+        tokio::spawn(summary::run(shared, Duration::from_secs(interval)));
+    }
+
+    /// Moves a running worker to `Succeeded` or `Failed` if it exited,
+    /// stopped heartbeating, or stopped making progress.
+    fn poll_worker(
+        &self,
+        worker: &mut Worker,
+        heartbeat: Option<&WorkerHeartbeat>,
+        checkpoint_step: Option<u64>,
+    ) {
+        let rank = worker.rank;
+        let pid = worker.pid();
+        let Some(process) = worker.process.as_mut() else {
+            self.shared.fail_worker(worker, FailureReason::PollError);
+            return;
+        };
+
+        match process.try_wait() {
+            Ok(Some(status)) => {
+                self.shared.journal.record(Event::WorkerExited {
+                    rank,
+                    pid,
+                    code: status.code,
+                    signal: status.signal,
+                    lifetime_secs: now_secs() - worker.started_at,
+                });
+                if status == ExitInfo::UNKNOWN {
+                    // Adopted, so its exit status is lost; go by what it left
+                    // on disk instead.
+                    if self.adopted_rank_done(rank) {
+                        info!("[coord] adopted worker rank={} exited after finishing its work", rank);
+                        worker.reaped();
+                        worker.state = WorkerState::Succeeded;
+                    } else {
+                        warn!("[coord] adopted worker rank={} exited before finishing its work", rank);
+                        let reason = FailureReason::AdoptedExit;
+                        self.shared.journal.record(Event::failed(rank, pid, reason));
+                        worker.fail(reason);
+                    }
+                } else if status.success() {
+                    info!("[coord] worker rank={} completed (exit 0)", rank);
+                    worker.reaped();
+                    worker.state = WorkerState::Succeeded;
+                } else if status.stopped() {
+                    // SIGTERMed by someone else (an eviction, an operator's
+                    // kill): it checkpointed but is not done, so it resumes.
+                    warn!("[coord] worker rank={} stopped on SIGTERM before finishing ({})", rank, status);
+                    let reason = FailureReason::exited(status);
+                    self.shared.journal.record(Event::failed(rank, pid, reason));
+                    worker.fail(reason);
+                } else {
+                    warn!("[coord] worker rank={} exited ({})", rank, status);
+                    // The reaped child no longer reports its pid.
+                    let reason = FailureReason::exited(status);
+                    self.shared.journal.record(Event::failed(rank, pid, reason));
+                    worker.fail(reason);
+                }
+            }
+            Ok(None) => {
+                // Still running; the spawn time counts as the first heartbeat
+                // so a fresh process gets a full timeout to report in.
+                if let Some(hb) = heartbeat {
+                    worker.observe_heartbeat(hb, hb.timestamp);
+                }
+                if now_secs() - worker.last_heartbeat >= self.heartbeat_timeout.as_secs_f64() {
+                    warn!("[coord] worker rank={} heartbeat timeout!", rank);
+                    self.shared.fail_worker(worker, FailureReason::HeartbeatTimeout);
+                } else if self.is_hung(worker, checkpoint_step) {
+                    warn!(
+                        "[coord] worker rank={} made no progress for {:?}; treating as hung",
+                        rank,
+                        self.progress_timeout.unwrap_or_default()
+                    );
+                    self.shared.fail_worker(worker, FailureReason::Hung);
+                }
+            }
+            Err(e) => {
+                error!("[coord] failed to check worker {}: {}", rank, e);
+                self.shared.fail_worker(worker, FailureReason::PollError);
+            }
+        }
+    }
+
+    /// Whether an adopted rank got through all of its work: every epoch of
+    /// the lease queue is done, or its last checkpoint is at the end of the
+    /// shards assigned to it.
+    fn adopted_rank_done(&self, rank: usize) -> bool {
+        if let Some(progress) = self.shared.lease_progress() {
+            return progress.epoch >= progress.epochs;
+        }
+        let manifest = self.shared.manifest.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        let Some(manifest) = manifest else {
+            return false;
+        };
+        manifest.rank_done(rank, shards::checkpoint_position(&self.shared.config, &manifest, rank))
+    }
+
+    /// Progress watchdog. A rank whose heartbeat step and LATEST checkpoint
+    /// both stay unchanged for `progress_timeout` is hung. If a dump signal
+    /// is configured it is sent first, and the rank is only declared hung
+    /// once `hang_dump_grace` has passed.
+    fn is_hung(&self, worker: &mut Worker, checkpoint_step: Option<u64>) -> bool {
+        let Some(timeout) = self.progress_timeout else {
+            return false;
+        };
+        let now = now_secs();
+        let steps = (worker.progress.as_ref().and_then(|p| p.step), checkpoint_step);
+        if worker.last_steps != Some(steps) {
+            worker.last_steps = Some(steps);
+            worker.step_advanced_at = now;
+            worker.dump_requested_at = None;
+            return false;
+        }
+        if now - worker.step_advanced_at < timeout.as_secs_f64() {
+            return false;
+        }
+
+        let Some(signal) = self.hang_dump_signal else {
+            return true;
+        };
+        match worker.dump_requested_at {
+            None => {
+                if let Some(pid) = worker.pid() {
+                    warn!("[coord] worker rank={} appears hung; requesting stack dump", worker.rank);
+                    if let Err(e) = send_signal(pid, signal) {
+                        warn!("[coord] failed to signal rank={} pid={}: {}", worker.rank, pid, e);
+                    }
+                }
+                worker.dump_requested_at = Some(now);
+                false
+            }
+            Some(at) => now - at >= self.hang_dump_grace.as_secs_f64(),
+        }
+    }
+
+    /// Consults the restart policy for a newly failed rank: either gives up
+    /// on it or hands it to a restart task that fires after the backoff.
+    fn schedule_restart(&self, worker: &mut Worker) {
+        let rank = worker.rank;
+        let now = Instant::now();
+        let crashed = worker.last_failure.is_some_and(|r| r.is_crash());
+        match self.restart_policy.on_failure(&mut worker.history, worker.restarts, crashed, now) {
+            RestartDecision::GiveUp(reason) => {
+                warn!("[coord] rank={} {}; not restarting", rank, reason);
+                worker.state = WorkerState::Exhausted;
+                self.shared.journal.record(Event::GaveUp {
+                    rank,
+                    restarts: worker.restarts,
+                    reason: reason.label(),
+                });
+            }
+            RestartDecision::Restart(delay) => {
+                let reason = worker.last_failure.map_or("unknown".to_string(), |r| r.to_string());
+                info!("[coord] rank={} failed ({}); restart scheduled in {:?}", rank, reason, delay);
+                worker.restart_at = Some(now + delay);
+                let shared = self.shared.clone();
+                let attempt = worker.restarts + 1;
+                shared.journal.record(Event::RestartScheduled {
+                    rank,
+                    attempt,
+                    delay_ms: delay.as_millis() as u64,
+                });
+                let max_restarts = self.max_restarts;
+                worker.restart_task = Some(tokio::spawn(async move {
+                    sleep(delay).await;
+                    info!("[coord] restarting rank={} (attempt {}/{})", rank, attempt, max_restarts);
+                    restart_worker(&shared, rank, attempt).await;
+                }));
+            }
+        }
+    }
+
+    async fn monitor_workers(&mut self, signals: &mut ShutdownSignals, hangup: &mut Signal) -> JobOutcome {
+        let mut saved = None;
+        loop {
+            let stop_reason = tokio::select! {
+                _ = sleep(Duration::from_millis(500)) => None,
+                name = signals.recv() => Some(format!("received {}", name)),
+                _ = self.shared.stop.notified() => Some("stop requested".to_string()),
+                _ = hangup.recv() => {
+                    self.reload_world_size();
+                    None
+                }
+                _ = self.shared.rescale_requested.notified() => {
+                    let requested = self.shared.rescale.lock().unwrap_or_else(|e| e.into_inner()).take();
+                    if let Some(world_size) = requested {
+                        self.rescale(world_size).await;
+                        self.save_state(&mut saved);
+                    }
+                    None
+                }
+            };
+            if let Some(reason) = stop_reason {
+                info!("[coord] {}; shutting down", reason);
+                self.shared.journal.record(Event::ShutdownStarted { reason });
+                let aborted = self.shared.barrier().abort("job is stopping");
+                self.record_barrier(aborted);
+                let outcome = shutdown::drain(&self.shared, self.shutdown_grace).await;
+                info!("[coord] shutdown finished: {}", outcome);
+                self.shared.journal.record(Event::JobFinished { outcome });
+                self.save_state(&mut saved);
+                return outcome;
+            }
+
+            let running = self.running_ranks();
+            let heartbeats = self.read_heartbeat_files(&running);
+            let checkpoint_steps = self.read_checkpoint_steps(&running);
+
+            let now = now_secs();
+            let mut workers = self.shared.workers();
+            let mut step_rates = self.shared.step_rates();
+            for worker in workers.values_mut() {
+                if worker.state == WorkerState::Draining {
+                    poll_draining(&self.shared.journal, worker);
+                }
+                if worker.state == WorkerState::Running {
+                    let heartbeat = heartbeats.get(&worker.rank);
+                    let checkpoint_step = checkpoint_steps.get(&worker.rank).copied();
+                    self.poll_worker(worker, heartbeat, checkpoint_step);
+                    let step = worker.progress.as_ref().and_then(|p| p.step).or(checkpoint_step);
+                    if let (WorkerState::Running, Some(step)) = (worker.state, step) {
+                        step_rates.entry(worker.rank).or_default().observe(now, step);
+                    }
+                }
+                if worker.state == WorkerState::Failed && worker.restart_at.is_none() {
+                    self.schedule_restart(worker);
+                }
+            }
+
+            let finished = workers
+                .values()
+                .all(|w| w.state.is_terminal())
+                .then(|| self.finish(&workers));
+            let ranks: Vec<(usize, WorkerState)> = workers.values().map(|w| (w.rank, w.state)).collect();
+            drop(step_rates);
+            drop(workers);
+            if finished.is_none() {
+                let event = self.shared.barrier().poll(now_secs(), self.shared.generation(), &ranks);
+                self.record_barrier(event);
+            }
+            self.expire_leases();
+            if finished.is_none() {
+                self.check_stragglers();
+            }
+            self.save_state(&mut saved);
+            if let Some(outcome) = finished {
+                return outcome;
+            }
+        }
+    }
+
+    /// Logs and journals the outcome once every rank is terminal.
+    fn finish(&self, workers: &HashMap<usize, Worker>) -> JobOutcome {
+        let outcome = JobOutcome::from_states(workers.values().map(|w| w.state));
+        info!("[coord] all workers done. job finished: {}", outcome);
+        self.shared.journal.record(Event::JobFinished { outcome });
+        let mut sorted: Vec<&Worker> = workers.values().collect();
+        sorted.sort_by_key(|w| w.rank);
+        for w in sorted {
+            let step = w.progress.as_ref().and_then(|p| p.step);
+            info!(
+                "[coord] rank={} state={} restarts={} step={}",
+                w.rank,
+                w.state,
+                w.restarts,
+                step.map_or("-".to_string(), |s| s.to_string())
+            );
+        }
+        outcome
+    }
+
+    /// On SIGHUP, re-reads `world_size` from the job file and requests a
+    /// rescale if it changed. Only applies if the file is where the
+    /// setting comes from; the environment and CLI cannot change.
+    fn reload_world_size(&self) {
+        let Some(Source::File(path)) = self.shared.config_sources.get("world_size") else {
+            warn!("[coord] SIGHUP: world_size is not set by the config file; ignoring");
+            return;
+        };
+        let world_size = match config::read_key(path, "world_size") {
+            Ok(Some(value)) => match value.as_u64() {
+                Some(n) => n as usize,
+                None => {
+                    warn!("[coord] SIGHUP: invalid world_size {} in {}", value, path.display());
+                    return;
+                }
+            },
+            Ok(None) => {
+                warn!("[coord] SIGHUP: {} no longer sets world_size; ignoring", path.display());
+                return;
+            }
+            Err(e) => {
+                warn!("[coord] SIGHUP: {:#}", e);
+                return;
+            }
+        };
+        if world_size == self.shared.generation().world_size {
+            info!("[coord] SIGHUP: world_size unchanged ({})", world_size);
+            return;
+        }
+        if let Err(e) = self.shared.request_rescale(world_size) {
+            warn!("[coord] SIGHUP: cannot change world_size: {}", e);
+        }
+    }
+
+    /// Changes the world size: stops every rank at a checkpoint, remaps the
+    /// unprocessed data onto `world_size` ranks, and relaunches them as the
+    /// next generation. If the remap fails the job is relaunched as it was.
+    async fn rescale(&mut self, world_size: usize) {
+        let from = self.shared.generation().world_size;
+        if world_size == from || self.shared.stopping.load(Ordering::SeqCst) {
+            return;
+        }
+        info!("[coord] changing world_size from {} to {}", from, world_size);
+        self.shared.journal.record(Event::RescaleStarted { from, to: world_size });
+        let aborted = self.shared.barrier().abort("world size is changing");
+        self.record_barrier(aborted);
+
+        let outcome = shutdown::drain(&self.shared, self.shutdown_grace).await;
+        if outcome != JobOutcome::Stopped {
+            warn!(
+                "[coord] not every rank stopped cleanly ({}); their data since the last checkpoint is redone",
+                outcome
+            );
+        }
+
+        if let Err(e) = self.remap_shards(world_size) {
+            error!("[coord] failed to change world_size to {}: {:#}", world_size, e);
+            self.shared.journal.record(Event::RescaleFailed {
+                to: world_size,
+                error: format!("{:#}", e),
+            });
+        }
+        self.relaunch().await;
+    }
+
+    /// Moves the job to the next generation at `world_size`, remapping the
+    /// shards from the stopped ranks' checkpoints.
+    fn remap_shards(&self, world_size: usize) -> Result<()> {
+        let from = self.shared.generation().world_size;
+        let manifest = self.shared.manifest.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        let Some(manifest) = manifest else {
+            bail!("changing the world size needs a shard manifest");
+        };
+        let next = shards::rescale(&self.shared.config, &manifest, world_size, &self.finished_ranks(world_size))?;
+        let generation = Generation { number: next.generation, world_size };
+        *self.shared.manifest.lock().unwrap_or_else(|e| e.into_inner()) = Some(next);
+        *self.shared.generation.lock().unwrap_or_else(|e| e.into_inner()) = generation;
+        if let Some(leases) = &self.shared.leases {
+            leases.lock().unwrap_or_else(|e| e.into_inner()).release_ranks(world_size);
+        }
+        info!("[coord] world_size is now {} (generation {})", world_size, generation.number);
+        self.shared.journal.record(Event::WorldSizeChanged {
+            generation: generation.number,
+            from,
+            to: world_size,
+        });
+        Ok(())
+    }
+
+    /// Ranks below `world_size` that succeeded or gave up, which sit out the
+    /// next generation. None do if that would leave no rank to run.
+    fn finished_ranks(&self, world_size: usize) -> BTreeSet<usize> {
+        let finished: BTreeSet<usize> = self
+            .shared
+            .workers()
+            .values()
+            .filter(|w| w.rank < world_size && matches!(w.state, WorkerState::Succeeded | WorkerState::Exhausted))
+            .map(|w| w.rank)
+            .collect();
+        if finished.len() == world_size {
+            return BTreeSet::new();
+        }
+        finished
+    }
+
+    /// Starts a fresh process for every unfinished rank of the current
+    /// generation and drops ranks beyond it. Restart budgets carry over.
+    /// The ranks are claimed as `Pending` first and spawned outside the
+    /// lock.
+    async fn relaunch(&self) {
+        let world_size = self.shared.generation().world_size;
+        let finished = self.finished_ranks(world_size);
+        self.shared.stopping.store(false, Ordering::SeqCst);
+        let claimed: Vec<(usize, usize)> = {
+            let mut workers = self.shared.workers();
+            workers.retain(|rank, _| *rank < world_size);
+            (0..world_size)
+                .filter(|r| !finished.contains(r))
+                .map(|rank| {
+                    let w = workers.entry(rank).or_insert_with(|| Worker::new(rank));
+                    w.drain_deadline = None;
+                    w.state = WorkerState::Pending;
+                    (rank, w.restarts)
+                })
+                .collect()
+        };
+        for (rank, attempt) in claimed {
+            let spawned = self.shared.spawn_off_runtime(rank, attempt).await;
+            let _ = self.shared.finish_spawn(rank, spawned);
+        }
+    }
+
+    /// Logs and journals what a step of the checkpoint barrier did.
+    fn record_barrier(&self, event: Option<BarrierEvent>) {
+        let event = match event {
+            None => return,
+            Some(BarrierEvent::Started { epoch }) => {
+                info!("[coord] global checkpoint {}: waiting for every rank to commit", epoch);
+                Event::CheckpointBarrierStarted { epoch }
+            }
+            Some(BarrierEvent::Committed { checkpoint, duration_secs }) => {
+                info!(
+                    "[coord] global checkpoint {} committed in {:.1}s: {:?}",
+                    checkpoint.epoch, duration_secs, checkpoint.steps
+                );
+                Event::GlobalCheckpointCommitted {
+                    epoch: checkpoint.epoch,
+                    generation: checkpoint.generation,
+                    steps: checkpoint.steps,
+                    duration_secs,
+                }
+            }
+            Some(BarrierEvent::Aborted { epoch, reason }) => {
+                warn!("[coord] global checkpoint {} aborted: {}", epoch, reason);
+                Event::CheckpointBarrierAborted { epoch, reason }
+            }
+        };
+        self.shared.journal.record(event);
+    }
+
+    /// Puts leases whose holder stopped renewing them back into the queue.
+    fn expire_leases(&self) {
+        let Some(leases) = &self.shared.leases else {
+            return;
+        };
+        let expired = leases.lock().unwrap_or_else(|e| e.into_inner()).expire(now_secs());
+        for lease in expired {
+            warn!(
+                "[coord] lease {} of rank={} expired; requeued {} lines {}..{}",
+                lease.lease_id, lease.rank, lease.item.shard, lease.item.start_line, lease.item.end_line
+            );
+            self.shared.journal.record(Event::LeaseExpired {
+                rank: lease.rank,
+                lease_id: lease.lease_id,
+                epoch: lease.epoch,
+                item: lease.item,
+            });
+        }
+    }
+
+    /// Applies the straggler policy to running ranks that fell behind.
+    fn check_stragglers(&mut self) {
+        let now = now_secs();
+        if !self.stragglers.as_mut().is_some_and(|d| d.due(now)) {
+            return;
+        }
+        let rates = self.running_step_rates(now);
+        let Some(detector) = self.stragglers.as_mut() else {
+            return;
+        };
+        let policy = detector.policy;
+        for straggler in detector.check(now, &rates) {
+            self.mitigate_straggler(straggler, policy);
+        }
+    }
+
+    /// Steps per second of each running rank over the last minute, or
+    /// between its two newest checkpoints until that much is observed.
+    fn running_step_rates(&self, now: f64) -> BTreeMap<usize, f64> {
+        let running = self.running_ranks();
+        let windows = self.shared.step_rates();
+        running
+            .iter()
+            .filter_map(|&(rank, _)| {
+                let rate = windows.get(&rank).and_then(|w| w.rate(now)).or_else(|| {
+                    let dir = checkpoint::worker_dir(&self.checkpoint_dir, &self.job_id, rank);
+                    checkpoint::checkpoint_rate(&dir)
+                })?;
+                Some((rank, rate))
+            })
+            .collect()
+    }
+
+    fn mitigate_straggler(&self, straggler: Straggler, policy: StragglerPolicy) {
+        let Straggler { rank, steps_per_sec, median } = straggler;
+        warn!(
+            "[coord] rank={} is a straggler: {:.2} steps/s against a median of {:.2}; policy {}",
+            rank,
+            steps_per_sec,
+            median,
+            policy.label()
+        );
+        self.shared.journal.record(Event::StragglerDetected {
+            rank,
+            steps_per_sec,
+            median_steps_per_sec: median,
+            action: policy.label(),
+        });
+        match policy {
+            StragglerPolicy::Log => {}
+            StragglerPolicy::Restart => {
+                // The new process has to earn its own rate.
+                self.shared.step_rates().remove(&rank);
+                // Counts against the restart budget like any other failure.
+                let mut workers = self.shared.workers();
+                let Some(w) = workers.get_mut(&rank).filter(|w| w.state == WorkerState::Running) else {
+                    return;
+                };
+                self.shared.fail_worker(w, FailureReason::Straggler);
+                self.schedule_restart(w);
+            }
+            StragglerPolicy::Reshard => {
+                let Some(leases) = &self.shared.leases else {
+                    return;
+                };
+                let revoked = leases.lock().unwrap_or_else(|e| e.into_inner()).revoke(rank);
+                if let Some(lease) = revoked {
+                    info!(
+                        "[coord] revoked lease {} of rank={}; requeued {} lines {}..{}",
+                        lease.lease_id, rank, lease.item.shard, lease.item.start_line, lease.item.end_line
+                    );
+                    self.shared.journal.record(Event::LeaseRevoked {
+                        rank,
+                        lease_id: lease.lease_id,
+                        epoch: lease.epoch,
+                        item: lease.item,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Persists the worker map if it changed since `saved` was written.
+    fn save_state(&self, saved: &mut Option<JobState>) {
+        let snapshot = JobState::capture(
+            &self.job_id,
+            self.shared.generation(),
+            &self.shared.workers(),
+            self.shared.logs.enabled(),
+        );
+        if saved.as_ref().is_some_and(|s| s.same_as(&snapshot)) {
+            return;
+        }
+        match state::save(&self.state_path, &snapshot) {
+            Ok(()) => *saved = Some(snapshot),
+            Err(e) => warn!("[coord] failed to persist coordinator state: {:#}", e),
+        }
+    }
+
+    /// The state a previous coordinator of this job left behind, unless
+    /// resuming is disabled or that run already finished.
+    fn load_previous_state(&self) -> Result<Option<JobState>> {
+        if !self.shared.config.resume {
+            return Ok(None);
+        }
+        let Some(previous) = state::load(&self.state_path)? else {
+            return Ok(None);
+        };
+        if previous.finished() {
+            info!("[coord] previous run of this job finished; starting fresh");
+            return Ok(None);
+        }
+        info!(
+            "[coord] resuming from {} (written by pid {})",
+            self.state_path.display(),
+            previous.coordinator_pid
+        );
+        self.shared.journal.record(Event::JobResumed {
+            previous_pid: previous.coordinator_pid,
+            saved_at: previous.updated_at,
+        });
+        Ok(Some(previous))
+    }
+
+    /// SIGTERMs workers left behind by a previous coordinator and waits up
+    /// to `shutdown_grace` for them to checkpoint and exit before SIGKILLing
+    /// the rest.
+    async fn stop_orphans(&self, orphans: &[(usize, u32)]) {
+        for &(rank, pid) in orphans {
+            warn!("[coord] stopping orphaned worker rank={} pid={}", rank, pid);
+            if let Err(e) = send_signal(pid, libc::SIGTERM) {
+                warn!("[coord] failed to send SIGTERM to rank={} pid={}: {}", rank, pid, e);
+            }
+        }
+        let deadline = Instant::now() + self.shutdown_grace;
+        while orphans.iter().any(|&(_, pid)| process_alive(pid)) && Instant::now() < deadline {
+            sleep(Duration::from_millis(100)).await;
+        }
+        for &(rank, pid) in orphans {
+            let killed = process_alive(pid);
+            if killed {
+                warn!("[coord] orphaned worker rank={} did not stop within grace; killing", rank);
+                let _ = send_signal(pid, libc::SIGKILL);
+            }
+            self.shared.journal.record(Event::OrphanStopped { rank, pid, killed });
+        }
+    }
+
+    /// Builds a rank's worker, carrying over its restart budget from the
+    /// previous run. A live orphan is adopted unless its output was piped
+    /// to the previous coordinator; otherwise the rank is left `Pending`
+    /// for the caller to spawn, unless it already succeeded or was given up
+    /// on.
+    fn resume_rank(&self, rank: usize, previous: Option<&RankState>) -> Worker {
+        let mut worker = Worker::new(rank);
+        if let Some(prev) = previous {
+            worker.restarts = prev.restarts;
+            worker.restart_reasons = prev.restart_reasons.clone();
+            if matches!(prev.state, WorkerState::Succeeded | WorkerState::Exhausted) {
+                worker.state = prev.state;
+                return worker;
+            }
+            let adoptable = !prev.piped_output;
+            if let Some(pid) = prev.pid.filter(|&pid| adoptable && state::is_our_worker(pid, &self.job_id, rank)) {
+                info!("[coord] adopted worker rank={} pid={}", rank, pid);
+                worker.adopt(pid, prev.started_at);
+                self.shared.journal.record(Event::WorkerAdopted {
+                    rank,
+                    pid,
+                    restarts: worker.restarts,
+                });
+                return worker;
+            }
+        }
+        worker
+    }
+
+    pub async fn run(&mut self) -> Result<JobOutcome> {
+        let generation = self.shared.generation();
+        info!("[coord] job={}", self.job_id);
+        info!("[coord] world_size={} generation={}", generation.world_size, generation.number);
+        info!("[coord] checkpoints={}", self.checkpoint_dir.display());
+
+        let mut signals = if self.handle_signals { ShutdownSignals::new()? } else { ShutdownSignals::ignored() };
+        let mut hangup = signal(SignalKind::hangup())?;
+        self.start_heartbeat_listeners()?;
+        self.start_api()?;
+        self.start_checkpoint_gc();
+        self.start_summary();
+
+        self.shared.journal.record(Event::JobStarted {
+            world_size: generation.world_size,
+            generation: generation.number,
+        });
+        let mut previous: HashMap<usize, RankState> = HashMap::new();
+        let mut previous_generation = generation.number;
+        if let Some(s) = self.load_previous_state()? {
+            previous_generation = s.generation;
+            previous = s.workers.into_iter().map(|r| (r.rank, r)).collect();
+        }
+
+        // Orphans that will not be adopted must be gone before their
+        // replacements start, and before their checkpoints are read for a
+        // resize. Workers of another generation were launched with a
+        // different shard assignment, and workers whose output was piped to
+        // the previous coordinator die on their next write; neither can be
+        // adopted.
+        let resize_to = self.resize_to.take();
+        let kill_orphans = self.shared.config.orphan_policy == "kill"
+            || previous_generation != generation.number
+            || resize_to.is_some();
+        let orphans: Vec<(usize, u32)> = previous
+            .values()
+            .filter(|prev| kill_orphans || prev.piped_output || prev.rank >= generation.world_size)
+            .filter_map(|prev| Some((prev.rank, prev.pid?)))
+            .filter(|&(rank, pid)| state::is_our_worker(pid, &self.job_id, rank))
+            .collect();
+        self.stop_orphans(&orphans).await;
+        if let Some(world_size) = resize_to {
+            self.remap_shards(world_size)?;
+        }
+        let generation = self.shared.generation();
+        if previous_generation != generation.number {
+            // Every rank starts over on the new assignment.
+            for prev in previous.values_mut() {
+                prev.state = WorkerState::Stopped;
+                prev.pid = None;
+            }
+        }
+
+        // Adopt workers, then spawn the rest outside the lock
+        let mut claimed = Vec::new();
+        {
+            let mut workers = self.shared.workers();
+            for rank in 0..generation.world_size {
+                let worker = self.resume_rank(rank, previous.get(&rank));
+                if worker.state == WorkerState::Pending {
+                    claimed.push((rank, worker.restarts));
+                }
+                workers.insert(rank, worker);
+            }
+        }
+        for (i, &(rank, attempt)) in claimed.iter().enumerate() {
+            match self.shared.spawn_off_runtime(rank, attempt).await {
+                Ok(child) => {
+                    let _ = self.shared.finish_spawn(rank, Ok(child));
+                }
+                Err(e) => {
+                    // Nothing would watch the ranks started so far.
+                    let mut workers = self.shared.workers();
+                    for (rank, _) in &claimed[..i] {
+                        if let Some(w) = workers.get_mut(rank) {
+                            w.kill();
+                        }
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        info!("[coord] all workers spawned");
+
+        // Monitor workers
+        let outcome = self.monitor_workers(&mut signals, &mut hangup).await;
+        if self.shared.config.summary_interval > 0 {
+            summary::finish(&self.shared, outcome);
+        }
+
+        info!("[coord] coordinator shutdown");
+        Ok(outcome)
+    }
+}
+
+/// Finishes an operator drain once the process exits, killing it if it
+/// overstays its grace period.
+fn poll_draining(journal: &Journal, worker: &mut Worker) {
+    let rank = worker.rank;
+    let pid = worker.pid();
+    let mut exit_status = None;
+    let exited = match worker.process.as_mut().map(|p| p.try_wait()) {
+        Some(Ok(Some(status))) => {
+            info!("[coord] worker rank={} drained ({})", rank, status);
+            worker.reaped();
+            exit_status = Some(status);
+            true
+        }
+        Some(Ok(None)) => false,
+        Some(Err(e)) => {
+            error!("[coord] failed to check worker {}: {}", rank, e);
+            true
+        }
+        None => true,
+    };
+    let overdue = worker.drain_deadline.is_some_and(|d| now_secs() >= d);
+    if !exited && overdue {
+        warn!("[coord] worker rank={} did not drain within grace; killing", rank);
+    }
+    if exited || overdue {
+        journal.record(Event::WorkerStopped {
+            rank,
+            pid,
+            code: exit_status.and_then(|s| s.code),
+            signal: exit_status.and_then(|s| s.signal),
+            killed: exit_status.is_none(),
+        });
+        worker.kill();
+        worker.drain_deadline = None;
+        worker.state = WorkerState::Stopped;
+    }
+}
+
+/// Body of a scheduled restart. The rank is marked `Pending` under the
+/// lock before its process is spawned, so a rank that was dealt with while
+/// the task slept is never given a second process. A failed spawn leaves
+/// the worker `Failed` so the monitor loop consults the restart policy
+/// again.
+async fn restart_worker(shared: &Arc<Shared>, rank: usize, attempt: usize) {
+    if shared.stopping.load(Ordering::SeqCst) {
+        return;
+    }
+    {
+        let mut workers = shared.workers();
+        let Some(w) = workers.get_mut(&rank) else {
+            return;
+        };
+        w.restart_task = None;
+        w.restart_at = None;
+        if w.state != WorkerState::Failed {
+            // The rank was dealt with while this task slept.
+            return;
+        }
+        w.restarts += 1;
+        w.record_restart(w.last_failure.map_or("unknown", |r| r.label()));
+        w.state = WorkerState::Pending;
+    }
+    let spawned = shared.spawn_off_runtime(rank, attempt).await;
+    let _ = shared.finish_spawn(rank, spawned);
+}
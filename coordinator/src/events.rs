@@ -0,0 +1,366 @@
+use crate::coordinator::now_secs;
+use crate::leases::WorkItem;
+use crate::worker::{FailureReason, JobOutcome};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::warn;
+
+/// `checkpoint_dir/<job>/events.jsonl`.
+pub fn journal_path(checkpoint_dir: &Path, job_id: &str) -> PathBuf {
+    checkpoint_dir.join(job_id).join("events.jsonl")
+}
+
+/// One coordinator decision. Serialized with an `event` tag next to the
+/// [`Record`] fields, one JSON object per line.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    JobStarted {
+        world_size: usize,
+        generation: u64,
+    },
+    /// Picked up from the state a previous coordinator left behind.
+    JobResumed {
+        previous_pid: u32,
+        saved_at: f64,
+    },
+    /// A live worker of the previous coordinator was taken over.
+    WorkerAdopted {
+        rank: usize,
+        pid: u32,
+        restarts: usize,
+    },
+    /// A live worker of the previous coordinator was stopped instead.
+    OrphanStopped {
+        rank: usize,
+        pid: u32,
+        killed: bool,
+    },
+    /// `attempt` is 0 for the first process of a rank.
+    WorkerSpawned {
+        rank: usize,
+        pid: Option<u32>,
+        attempt: usize,
+    },
+    SpawnFailed {
+        rank: usize,
+        attempt: usize,
+        error: String,
+    },
+    /// The process exited on its own.
+    WorkerExited {
+        rank: usize,
+        pid: Option<u32>,
+        code: Option<i32>,
+        signal: Option<i32>,
+        lifetime_secs: f64,
+    },
+    /// The rank moved to `failed`; any process still alive was killed.
+    WorkerFailed {
+        rank: usize,
+        pid: Option<u32>,
+        reason: &'static str,
+        code: Option<i32>,
+        signal: Option<i32>,
+    },
+    RestartScheduled {
+        rank: usize,
+        attempt: usize,
+        delay_ms: u64,
+    },
+    /// An operator asked for an immediate restart.
+    RestartRequested {
+        rank: usize,
+    },
+    GaveUp {
+        rank: usize,
+        restarts: usize,
+        reason: &'static str,
+    },
+    DrainRequested {
+        rank: usize,
+        pid: Option<u32>,
+    },
+    /// The rank reached `stopped`. `killed` is set if its process had to
+    /// be SIGKILLed after the grace period.
+    WorkerStopped {
+        rank: usize,
+        pid: Option<u32>,
+        code: Option<i32>,
+        signal: Option<i32>,
+        killed: bool,
+    },
+    /// A rank took a work item from the shard lease queue.
+    LeaseGranted {
+        rank: usize,
+        lease_id: u64,
+        epoch: usize,
+        #[serde(flatten)]
+        item: WorkItem,
+    },
+    LeaseCompleted {
+        rank: usize,
+        lease_id: u64,
+        epoch: usize,
+        #[serde(flatten)]
+        item: WorkItem,
+    },
+    /// Not renewed in time; the item went back into the queue.
+    LeaseExpired {
+        rank: usize,
+        lease_id: u64,
+        epoch: usize,
+        #[serde(flatten)]
+        item: WorkItem,
+    },
+    /// Taken away from a straggler and put back at the front of the queue.
+    LeaseRevoked {
+        rank: usize,
+        lease_id: u64,
+        epoch: usize,
+        #[serde(flatten)]
+        item: WorkItem,
+    },
+    /// Every work item of `epoch` (zero-based) was completed.
+    EpochCompleted {
+        epoch: usize,
+    },
+    /// All ranks are being stopped to change the world size.
+    RescaleStarted {
+        from: usize,
+        to: usize,
+    },
+    /// Shards were remapped and the ranks relaunched as `generation`.
+    WorldSizeChanged {
+        generation: u64,
+        from: usize,
+        to: usize,
+    },
+    RescaleFailed {
+        to: usize,
+        error: String,
+    },
+    /// Ranks were asked to checkpoint for global checkpoint `epoch`.
+    CheckpointBarrierStarted {
+        epoch: u64,
+    },
+    /// Every rank committed; `GLOBAL_LATEST` now lists these steps.
+    GlobalCheckpointCommitted {
+        epoch: u64,
+        generation: u64,
+        steps: BTreeMap<usize, u64>,
+        duration_secs: f64,
+    },
+    CheckpointBarrierAborted {
+        epoch: u64,
+        reason: String,
+    },
+    /// The rank's `LATEST` checkpoint failed verification before a launch
+    /// and was moved to step `to`, or removed if no step was valid.
+    CheckpointRepaired {
+        rank: usize,
+        from: String,
+        to: Option<u64>,
+        reason: String,
+    },
+    /// A checkpoint GC pass deleted expired steps of the rank and any
+    /// `step_*_tmp_*` directories left by interrupted writes.
+    CheckpointsCollected {
+        rank: usize,
+        steps: Vec<u64>,
+        orphans: usize,
+        bytes_reclaimed: u64,
+    },
+    /// The rank's step rate stayed below `straggler_threshold` times the
+    /// median for `straggler_grace`; `action` is the policy applied.
+    StragglerDetected {
+        rank: usize,
+        steps_per_sec: f64,
+        median_steps_per_sec: f64,
+        action: &'static str,
+    },
+    /// The scheduler daemon stopped the job to make room for a job of
+    /// higher priority. The job is queued again and resumes later.
+    JobPreempted {
+        by_job: String,
+        by_priority: i64,
+    },
+    ShutdownStarted {
+        reason: String,
+    },
+    JobFinished {
+        outcome: JobOutcome,
+    },
+}
+
+impl Event {
+    pub fn failed(rank: usize, pid: Option<u32>, reason: FailureReason) -> Self {
+        let (code, signal) = match reason {
+            FailureReason::Exited { code, signal } => (code, signal),
+            _ => (None, None),
+        };
+        Event::WorkerFailed {
+            rank,
+            pid,
+            reason: reason.label(),
+            code,
+            signal,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    ts: f64,
+    job_id: &'a str,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+/// Append-only JSONL journal of [`Event`]s.
+pub struct Journal {
+    job_id: String,
+    file: Mutex<File>,
+    /// Write errors are logged once, not on every event.
+    write_failed: AtomicBool,
+}
+
+impl Journal {
+    pub fn open(checkpoint_dir: &Path, job_id: &str) -> Result<Self> {
+        let path = journal_path(checkpoint_dir, job_id);
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create {}", dir.display()))?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("failed to open event journal {}", path.display()))?;
+        Ok(Self {
+            job_id: job_id.to_string(),
+            file: Mutex::new(file),
+            write_failed: AtomicBool::new(false),
+        })
+    }
+
+    pub fn record(&self, event: Event) {
+        let record = Record {
+            ts: now_secs(),
+            job_id: &self.job_id,
+            event: &event,
+        };
+        let mut line = match serde_json::to_string(&record) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("[coord] failed to serialize event {:?}: {}", event, e);
+                return;
+            }
+        };
+        line.push('\n');
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            if !self.write_failed.swap(true, Ordering::Relaxed) {
+                warn!("[coord] failed to write event journal: {}", e);
+            }
+        }
+    }
+}
+
+/// Which journal lines `coordinator events` prints.
+#[derive(Debug, Default)]
+pub struct Filter {
+    pub ranks: Vec<usize>,
+    pub events: Vec<String>,
+    pub since: Option<f64>,
+}
+
+impl Filter {
+    fn matches(&self, line: &str) -> bool {
+        let Ok(v) = serde_json::from_str::<Value>(line) else {
+            return false;
+        };
+        if !self.ranks.is_empty() {
+            let rank = v.get("rank").and_then(Value::as_u64);
+            if !rank.is_some_and(|r| self.ranks.contains(&(r as usize))) {
+                return false;
+            }
+        }
+        if !self.events.is_empty() {
+            let event = v.get("event").and_then(Value::as_str).unwrap_or_default();
+            if !self.events.iter().any(|e| e == event) {
+                return false;
+            }
+        }
+        if let Some(since) = self.since {
+            if v.get("ts").and_then(Value::as_f64).unwrap_or_default() < since {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Prints matching journal lines, the last `lines` of them if set, then
+/// keeps printing new ones as they are appended if `follow` is set.
+pub async fn tail(path: &Path, filter: &Filter, lines: Option<usize>, follow: bool) -> Result<()> {
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let mut reader = BufReader::new(file);
+
+    let mut backlog = VecDeque::new();
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 0 {
+        if filter.matches(&line) {
+            backlog.push_back(line.trim_end().to_string());
+            if lines.is_some_and(|n| backlog.len() > n) {
+                backlog.pop_front();
+            }
+        }
+        line.clear();
+    }
+    let mut stdout = std::io::stdout().lock();
+    for l in backlog {
+        writeln!(stdout, "{}", l)?;
+    }
+    stdout.flush()?;
+    drop(stdout);
+
+    if !follow {
+        return Ok(());
+    }
+    let mut pos = reader.stream_position()?;
+    loop {
+        sleep(Duration::from_millis(500)).await;
+        let len = std::fs::metadata(path)?.len();
+        if len < pos {
+            // Truncated; start over.
+            pos = 0;
+        }
+        if len == pos {
+            continue;
+        }
+        reader.seek(SeekFrom::Start(pos))?;
+        loop {
+            let n = reader.read_line(&mut line)?;
+            // Only consume complete lines; a partial one is retried later.
+            if n == 0 || !line.ends_with('\n') {
+                line.clear();
+                break;
+            }
+            pos += n as u64;
+            if filter.matches(&line) {
+                println!("{}", line.trim_end());
+            }
+            line.clear();
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use crate::coordinator::{now_secs, Shared};
+use crate::leases;
+use crate::worker::Worker;
+use anyhow::{Context, Result};
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::post;
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::net::{TcpListener, UdpSocket};
+use tracing::{debug, info, warn};
+
+fn v1() -> u32 {
+    1
+}
+
+/// Liveness message a worker writes to its HEARTBEAT file or pushes to the
+/// coordinator's listener.
+///
+/// Version 1 carried only `timestamp`, `rank`, and `pid`; version 2 adds
+/// the [`Progress`] fields. Unknown fields are ignored so newer workers
+/// keep working against this coordinator.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkerHeartbeat {
+    /// Missing in version 1 payloads.
+    #[serde(default = "v1")]
+    pub version: u32,
+    pub timestamp: f64,
+    pub rank: usize,
+    pub pid: u32,
+    #[serde(flatten)]
+    pub progress: Progress,
+}
+
+/// Training progress reported by a worker, mirroring its state.json.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Progress {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub step: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shard_idx: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line_idx: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loss: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub samples_per_sec: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_checkpoint_step: Option<u64>,
+}
+
+/// Reads the `HEARTBEAT` file in a rank's worker directory.
+pub fn read_file(worker_dir: &Path) -> Option<WorkerHeartbeat> {
+    let content = std::fs::read_to_string(worker_dir.join("HEARTBEAT")).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rejection {
+    UnknownRank(usize),
+    /// The heartbeat comes from a process that is not the rank's current one.
+    StalePid { rank: usize, pid: u32, current: Option<u32> },
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::UnknownRank(rank) => write!(f, "unknown rank {}", rank),
+            Rejection::StalePid { rank, pid, current: Some(current) } => {
+                write!(f, "stale pid {} for rank {} (current pid {})", pid, rank, current)
+            }
+            Rejection::StalePid { rank, pid, current: None } => {
+                write!(f, "stale pid {} for rank {} (no running process)", pid, rank)
+            }
+        }
+    }
+}
+
+/// Checks that a message claiming to come from `rank` was sent by the
+/// rank's current process.
+pub fn check_sender(workers: &HashMap<usize, Worker>, rank: usize, pid: u32) -> Result<(), Rejection> {
+    let worker = workers.get(&rank).ok_or(Rejection::UnknownRank(rank))?;
+    let current = worker.pid();
+    if current != Some(pid) {
+        return Err(Rejection::StalePid { rank, pid, current });
+    }
+    Ok(())
+}
+
+/// Records a pushed heartbeat. Liveness is stamped with the coordinator's
+/// clock so skew between hosts does not matter.
+pub fn record(shared: &Shared, hb: &WorkerHeartbeat) -> Result<(), Rejection> {
+    let now = now_secs();
+    {
+        let mut workers = shared.workers();
+        check_sender(&workers, hb.rank, hb.pid)?;
+        if let Some(worker) = workers.get_mut(&hb.rank) {
+            worker.observe_heartbeat(hb, now);
+        }
+    }
+    Ok(())
+}
+
+/// Serves `POST /heartbeat` with a [`WorkerHeartbeat`] JSON body, and the
+/// shard lease endpoints if leasing is enabled.
+pub async fn serve_http(shared: Arc<Shared>, addr: SocketAddr) -> Result<()> {
+    let mut app = Router::new().route("/heartbeat", post(http_heartbeat));
+    if shared.leases.is_some() {
+        app = app.merge(leases::routes());
+    }
+    let app = app.with_state(shared);
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("failed to bind heartbeat listener on {}", addr))?;
+    info!("[coord] heartbeat listener on http://{}/heartbeat", addr);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+async fn http_heartbeat(
+    State(shared): State<Arc<Shared>>,
+    Json(hb): Json<WorkerHeartbeat>,
+) -> (StatusCode, String) {
+    match record(&shared, &hb) {
+        Ok(()) => (StatusCode::NO_CONTENT, String::new()),
+        Err(r @ Rejection::UnknownRank(_)) => (StatusCode::NOT_FOUND, r.to_string()),
+        Err(r @ Rejection::StalePid { .. }) => (StatusCode::CONFLICT, r.to_string()),
+    }
+}
+
+/// Accepts one [`WorkerHeartbeat`] JSON document per datagram.
+pub async fn serve_udp(shared: Arc<Shared>, addr: SocketAddr) -> Result<()> {
+    let socket = UdpSocket::bind(addr)
+        .await
+        .with_context(|| format!("failed to bind heartbeat socket on udp://{}", addr))?;
+    info!("[coord] heartbeat listener on udp://{}", addr);
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let (len, peer) = socket.recv_from(&mut buf).await?;
+        let hb = match serde_json::from_slice::<WorkerHeartbeat>(&buf[..len]) {
+            Ok(hb) => hb,
+            Err(e) => {
+                debug!("[coord] malformed heartbeat from {}: {}", peer, e);
+                continue;
+            }
+        };
+        if let Err(r) = record(&shared, &hb) {
+            warn!("[coord] rejected heartbeat from {}: {}", peer, r);
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use crate::config::Config;
+use crate::coordinator::Generation;
+use crate::shards;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::Stdio;
+use tokio::process::{Child, Command};
+use tracing::info;
+
+/// Rank-specific replacement for parts of the default worker command,
+/// e.g. running an evaluator on rank 0.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorkerOverride {
+    /// Ranks this override applies to.
+    pub ranks: Vec<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub program: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub args: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+    /// Merged on top of `worker_env`.
+    pub env: BTreeMap<String, String>,
+}
+
+/// The concrete process to launch for one rank, with placeholders expanded.
+///
+/// Supported placeholders in args, cwd, and env values: `{rank}`,
+/// `{world_size}` (the current one), `{job_id}`, `{checkpoint_dir}`,
+/// `{dataset_dir}`.
+#[derive(Debug, Clone)]
+pub struct LaunchSpec {
+    pub program: String,
+    pub args: Vec<String>,
+    pub cwd: String,
+    pub env: BTreeMap<String, String>,
+}
+
+impl LaunchSpec {
+    pub fn for_rank(config: &Config, world_size: usize, rank: usize) -> Self {
+        let mut program = config.worker_program.clone();
+        let mut args = config.worker_args.clone();
+        let mut cwd = config.worker_cwd.clone();
+        let mut env = config.worker_env.clone();
+
+        // Later overrides win when several list the same rank.
+        for o in config.worker_overrides.iter().filter(|o| o.ranks.contains(&rank)) {
+            if let Some(p) = &o.program {
+                program = p.clone();
+            }
+            if let Some(a) = &o.args {
+                args = a.clone();
+            }
+            if let Some(c) = &o.cwd {
+                cwd = c.clone();
+            }
+            env.extend(o.env.clone());
+        }
+
+        let expand = |s: &str| expand_placeholders(s, config, world_size, rank);
+        Self {
+            program: expand(&program),
+            args: args.iter().map(|a| expand(a)).collect(),
+            cwd: expand(&cwd),
+            env: env.into_iter().map(|(k, v)| (k, expand(&v))).collect(),
+        }
+    }
+
+    /// Builds the command; `base_env` is applied first so the configured
+    /// env can override it.
+    pub fn command(&self, base_env: &[(&str, String)]) -> Command {
+        let mut cmd = Command::new(&self.program);
+        cmd.args(&self.args);
+        cmd.current_dir(&self.cwd);
+        for (k, v) in base_env {
+            cmd.env(k, v);
+        }
+        cmd.envs(&self.env);
+        // Own process group, so a terminal Ctrl-C reaches only the
+        // coordinator, which then drains the workers with SIGTERM.
+        cmd.process_group(0);
+        cmd
+    }
+}
+
+fn expand_placeholders(s: &str, config: &Config, world_size: usize, rank: usize) -> String {
+    s.replace("{rank}", &rank.to_string())
+        .replace("{world_size}", &world_size.to_string())
+        .replace("{job_id}", &config.job_id)
+        .replace("{checkpoint_dir}", &config.checkpoint_dir)
+        .replace("{dataset_dir}", &config.dataset_dir)
+}
+
+/// Launches the worker process for `rank` of `generation` with the
+/// standard worker env. `resume_step` is the rank's step in the last global
+/// checkpoint, which it restarts from instead of its own latest one.
+pub fn spawn_worker(config: &Config, generation: Generation, rank: usize, resume_step: Option<u64>) -> Result<Child> {
+    let mut env_vars = vec![
+        ("JOB_ID", config.job_id.clone()),
+        ("RANK", rank.to_string()),
+        ("WORLD_SIZE", generation.world_size.to_string()),
+        ("GENERATION", generation.number.to_string()),
+        ("CHECKPOINT_DIR", config.checkpoint_dir.clone()),
+        ("CHECKPOINT_EVERY", config.checkpoint_every.to_string()),
+        ("SLEEP_SEC", config.sleep_sec.to_string()),
+        ("DATASET_DIR", config.dataset_dir.clone()),
+        ("USE_S3", if config.use_s3 { "1" } else { "0" }.to_string()),
+    ];
+    if let Some(step) = resume_step {
+        env_vars.push(("RESUME_STEP", step.to_string()));
+    }
+    let host = &config.heartbeat_advertise_host;
+    if config.heartbeat_http_port != 0 {
+        let url = format!("http://{}:{}/heartbeat", host, config.heartbeat_http_port);
+        env_vars.push(("HEARTBEAT_URL", url));
+    }
+    if config.heartbeat_udp_port != 0 {
+        env_vars.push(("HEARTBEAT_UDP", format!("{}:{}", host, config.heartbeat_udp_port)));
+    }
+    if config.shard_leases {
+        env_vars.push(("LEASE_URL", format!("http://{}:{}", host, config.heartbeat_http_port)));
+    }
+    // Written by `shards::prepare` before any worker starts.
+    let manifest = shards::manifest_path(Path::new(&config.checkpoint_dir), &config.job_id);
+    if config.shard_manifest && !config.use_s3 && manifest.exists() {
+        let manifest = std::path::absolute(&manifest).unwrap_or(manifest);
+        env_vars.push(("SHARD_MANIFEST_PATH", manifest.display().to_string()));
+    }
+
+    let spec = LaunchSpec::for_rank(config, generation.world_size, rank);
+    let mut cmd = spec.command(&env_vars);
+    if config.log_dir.is_empty() {
+        cmd.stdout(Stdio::inherit()).stderr(Stdio::inherit());
+    } else {
+        // Captured by `logs::WorkerLogs::attach`.
+        cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+    }
+
+    let child = cmd
+        .spawn()
+        .with_context(|| format!("failed to launch `{}` for rank {}", spec.program, rank))?;
+    info!(
+        "[coord] spawned worker rank={} pid={} cmd={} {}",
+        rank,
+        child.id().unwrap_or(0),
+        spec.program,
+        spec.args.join(" ")
+    );
+
+    Ok(child)
+}
@@ -0,0 +1,639 @@
+use crate::config::Config;
+use crate::coordinator::{now_secs, Shared};
+use crate::events::Event;
+use crate::heartbeat::{self, Rejection};
+use crate::shards::ShardManifest;
+use crate::state::write_atomic;
+use anyhow::{Context, Result};
+use axum::extract::{Path as UrlPath, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{info, warn};
+
+/// `checkpoint_dir/<job>/leases.json`.
+pub fn leases_path(checkpoint_dir: &Path, job_id: &str) -> PathBuf {
+    checkpoint_dir.join(job_id).join("leases.json")
+}
+
+/// A unit of work: lines `start_line..end_line` of one shard.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct WorkItem {
+    pub shard: String,
+    pub start_line: u64,
+    pub end_line: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Lease {
+    pub lease_id: u64,
+    pub rank: usize,
+    pub epoch: usize,
+    #[serde(flatten)]
+    pub item: WorkItem,
+    /// Not persisted; a reloaded lease gets a fresh timeout.
+    #[serde(skip)]
+    pub expires_at: f64,
+    /// Holder's step when the lease was last renewed; it is only renewed
+    /// again once the step moves past it.
+    #[serde(skip)]
+    pub renewed_step: Option<u64>,
+}
+
+/// What a worker asking for work gets back.
+#[derive(Debug)]
+pub enum Grant {
+    Lease(Lease),
+    /// Everything left is leased to other ranks; ask again later, as an
+    /// expired lease may come back.
+    Wait,
+    /// Every epoch is done.
+    Done,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LeaseError {
+    /// Expired and requeued, already completed, or held by another rank.
+    NotHeld { lease_id: u64, rank: usize },
+}
+
+impl fmt::Display for LeaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LeaseError::NotHeld { lease_id, rank } => {
+                write!(f, "lease {} is not held by rank {}", lease_id, rank)
+            }
+        }
+    }
+}
+
+/// Global progress through the dataset.
+#[derive(Debug, Clone, Serialize)]
+pub struct LeaseProgress {
+    /// Zero-based; equals `epochs` once everything is done.
+    pub epoch: usize,
+    pub epochs: usize,
+    pub items: usize,
+    pub pending: usize,
+    pub leased: usize,
+    pub done: usize,
+    pub lines_done: u64,
+    pub lines_total: u64,
+}
+
+/// What survives a coordinator restart.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Saved {
+    epoch: usize,
+    next_id: u64,
+    done: Vec<WorkItem>,
+    leases: Vec<Lease>,
+}
+
+/// Work-stealing queue of [`WorkItem`]s: ranks lease the next item when
+/// they are ready instead of owning a fixed share of the dataset.
+///
+/// An item counts as processed only when the rank holding its lease
+/// reports completion. Leases are renewed by the holder's progress reports,
+/// so a worker that is alive but stuck loses its lease, and go back to the
+/// front of the queue when they expire. A holder whose lease expired or was
+/// revoked is refused renewal and completion, which tells it to drop the
+/// item. Each item is completed once per epoch, but work done under an
+/// expired lease may be repeated by the next holder. Progress is written to
+/// `leases.json` on every change.
+pub struct LeaseQueue {
+    path: PathBuf,
+    items: Vec<WorkItem>,
+    epochs: usize,
+    timeout: f64,
+    epoch: usize,
+    next_id: u64,
+    /// Indices into `items`.
+    pending: VecDeque<usize>,
+    done: BTreeSet<usize>,
+    leased: BTreeMap<u64, (usize, Lease)>,
+    /// Items revoked from each rank this epoch, which it is not leased
+    /// again. Not persisted.
+    revoked: HashMap<usize, BTreeSet<usize>>,
+}
+
+impl LeaseQueue {
+    /// Splits every shard in the manifest into items of at most
+    /// `lease_lines` lines (whole shards if 0), in manifest order.
+    fn new(path: PathBuf, manifest: &ShardManifest, config: &Config) -> Self {
+        let mut items = Vec::new();
+        for shard in &manifest.shards {
+            let step = if config.lease_lines == 0 { shard.lines } else { config.lease_lines };
+            let mut start = 0;
+            while start < shard.lines {
+                let end = (start + step).min(shard.lines);
+                items.push(WorkItem {
+                    shard: shard.name.clone(),
+                    start_line: start,
+                    end_line: end,
+                });
+                start = end;
+            }
+        }
+        Self {
+            path,
+            pending: (0..items.len()).collect(),
+            items,
+            epochs: config.epochs,
+            timeout: config.lease_timeout as f64,
+            epoch: 0,
+            next_id: 1,
+            done: BTreeSet::new(),
+            leased: BTreeMap::new(),
+            revoked: HashMap::new(),
+        }
+    }
+
+    /// Builds the queue for the job, picking up the progress a previous
+    /// coordinator saved unless resuming is disabled or it had finished.
+    pub fn open(manifest: &ShardManifest, config: &Config) -> Result<Self> {
+        let path = leases_path(Path::new(&config.checkpoint_dir), &config.job_id);
+        let mut queue = Self::new(path.clone(), manifest, config);
+        if !config.resume || !path.exists() {
+            return Ok(queue);
+        }
+        let content = std::fs::read_to_string(&path).with_context(|| format!("failed to read {}", path.display()))?;
+        let saved: Saved = serde_json::from_str(&content)
+            .with_context(|| format!("invalid lease state in {} (delete it to start over)", path.display()))?;
+        if saved.epoch >= queue.epochs {
+            info!("[coord] previous run processed every epoch; leasing from the start");
+            return Ok(queue);
+        }
+        queue.restore(saved);
+        let p = queue.progress();
+        info!(
+            "[coord] resuming leases at epoch {}/{}: {} done, {} leased, {} pending",
+            p.epoch + 1,
+            p.epochs,
+            p.done,
+            p.leased,
+            p.pending
+        );
+        Ok(queue)
+    }
+
+    fn restore(&mut self, saved: Saved) {
+        let index: HashMap<&WorkItem, usize> = self.items.iter().enumerate().map(|(i, item)| (item, i)).collect();
+        let mut unknown = 0;
+        let mut done = BTreeSet::new();
+        for item in &saved.done {
+            match index.get(item) {
+                Some(&i) => {
+                    done.insert(i);
+                }
+                None => unknown += 1,
+            }
+        }
+        let now = now_secs();
+        let mut leased = BTreeMap::new();
+        for mut lease in saved.leases {
+            match index.get(&lease.item) {
+                Some(&i) if !done.contains(&i) => {
+                    lease.expires_at = now + self.timeout;
+                    leased.insert(lease.lease_id, (i, lease));
+                }
+                _ => unknown += 1,
+            }
+        }
+        if unknown > 0 {
+            // Only happens if lease_lines changed; those lines are redone.
+            warn!("[coord] {} saved lease entries no longer match a work item", unknown);
+        }
+        self.epoch = saved.epoch;
+        self.next_id = saved.next_id.max(1);
+        self.pending = (0..self.items.len())
+            .filter(|i| !done.contains(i) && !leased.values().any(|(j, _)| j == i))
+            .collect();
+        self.done = done;
+        self.leased = leased;
+    }
+
+    /// The rank's current lease (renewed), or the next pending item.
+    /// A rank holds one lease at a time, so a restarted worker gets back
+    /// the item its predecessor was working on.
+    pub fn acquire(&mut self, rank: usize, now: f64) -> Grant {
+        if let Some((_, lease)) = self.leased.values_mut().find(|(_, l)| l.rank == rank) {
+            // The new process resumes from a checkpoint, possibly at an
+            // earlier step than its predecessor reported.
+            lease.expires_at = now + self.timeout;
+            lease.renewed_step = None;
+            return Grant::Lease(lease.clone());
+        }
+        if self.epoch >= self.epochs {
+            return Grant::Done;
+        }
+        // Revoked items are left to other ranks, unless none is working.
+        let revoked = self.revoked.get(&rank).filter(|_| !self.leased.is_empty());
+        let next = self.pending.iter().position(|i| !revoked.is_some_and(|r| r.contains(i)));
+        let Some(i) = next.and_then(|pos| self.pending.remove(pos)) else {
+            return Grant::Wait;
+        };
+        let lease = Lease {
+            lease_id: self.next_id,
+            rank,
+            epoch: self.epoch,
+            item: self.items[i].clone(),
+            expires_at: now + self.timeout,
+            renewed_step: None,
+        };
+        self.next_id += 1;
+        self.leased.insert(lease.lease_id, (i, lease.clone()));
+        self.save();
+        Grant::Lease(lease)
+    }
+
+    /// Marks the leased item processed. Returns the lease and, if it was
+    /// the last item of an epoch, the number of the epoch it finished.
+    pub fn complete(&mut self, rank: usize, lease_id: u64) -> Result<(Lease, Option<usize>), LeaseError> {
+        let (i, lease) = match self.leased.remove(&lease_id) {
+            Some((i, lease)) if lease.rank == rank => (i, lease),
+            Some(other) => {
+                self.leased.insert(lease_id, other);
+                return Err(LeaseError::NotHeld { lease_id, rank });
+            }
+            None => return Err(LeaseError::NotHeld { lease_id, rank }),
+        };
+        self.done.insert(i);
+        let mut finished = None;
+        if self.done.len() == self.items.len() {
+            finished = Some(self.epoch);
+            self.epoch += 1;
+            self.done.clear();
+            self.revoked.clear();
+            if self.epoch < self.epochs {
+                self.pending = (0..self.items.len()).collect();
+            }
+        }
+        self.save();
+        Ok((lease, finished))
+    }
+
+    /// Extends the lease if its holder's training `step` advanced since
+    /// the last renewal, and returns whether it did. A lease the rank no
+    /// longer holds is an error: the item was handed to another rank.
+    pub fn renew(&mut self, rank: usize, lease_id: u64, step: u64, now: f64) -> Result<bool, LeaseError> {
+        let lease = match self.leased.get_mut(&lease_id) {
+            Some((_, lease)) if lease.rank == rank => lease,
+            _ => return Err(LeaseError::NotHeld { lease_id, rank }),
+        };
+        if lease.renewed_step.is_some_and(|s| step <= s) {
+            return Ok(false);
+        }
+        lease.renewed_step = Some(step);
+        lease.expires_at = now + self.timeout;
+        Ok(true)
+    }
+
+    /// Takes the rank's lease away and puts its item at the front of the
+    /// queue for another rank. The rank is not leased the item again this
+    /// epoch, and its completion report will be refused.
+    pub fn revoke(&mut self, rank: usize) -> Option<Lease> {
+        let id = self.leased.iter().find(|(_, (_, l))| l.rank == rank).map(|(id, _)| *id)?;
+        let (i, lease) = self.leased.remove(&id)?;
+        self.pending.push_front(i);
+        self.revoked.entry(rank).or_default().insert(i);
+        self.save();
+        Some(lease)
+    }
+
+    /// Requeues the leases of ranks at or above `world_size`, which no
+    /// longer exist after a rescale.
+    pub fn release_ranks(&mut self, world_size: usize) {
+        let released: Vec<u64> = self
+            .leased
+            .iter()
+            .filter(|(_, (_, l))| l.rank >= world_size)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in released.iter().rev() {
+            if let Some((i, _)) = self.leased.remove(id) {
+                self.pending.push_front(i);
+            }
+        }
+        if !released.is_empty() {
+            info!("[coord] requeued {} leases of removed ranks", released.len());
+            self.save();
+        }
+    }
+
+    /// Requeues leases that were not renewed in time, ahead of untouched
+    /// items, and returns them.
+    pub fn expire(&mut self, now: f64) -> Vec<Lease> {
+        let expired: Vec<u64> = self
+            .leased
+            .iter()
+            .filter(|(_, (_, l))| l.expires_at <= now)
+            .map(|(id, _)| *id)
+            .collect();
+        let mut leases = Vec::new();
+        for id in expired.into_iter().rev() {
+            if let Some((i, lease)) = self.leased.remove(&id) {
+                self.pending.push_front(i);
+                leases.push(lease);
+            }
+        }
+        if !leases.is_empty() {
+            self.save();
+        }
+        leases
+    }
+
+    pub fn progress(&self) -> LeaseProgress {
+        let lines = |i: &usize| self.items[*i].end_line - self.items[*i].start_line;
+        LeaseProgress {
+            epoch: self.epoch,
+            epochs: self.epochs,
+            items: self.items.len(),
+            pending: self.pending.len(),
+            leased: self.leased.len(),
+            done: self.done.len(),
+            lines_done: self.done.iter().map(lines).sum(),
+            lines_total: (0..self.items.len()).map(|i| lines(&i)).sum(),
+        }
+    }
+
+    fn save(&self) {
+        let saved = Saved {
+            epoch: self.epoch,
+            next_id: self.next_id,
+            done: self.done.iter().map(|i| self.items[*i].clone()).collect(),
+            leases: self.leased.values().map(|(_, l)| l.clone()).collect(),
+        };
+        let result = serde_json::to_vec(&saved)
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| write_atomic(&self.path, &bytes));
+        if let Err(e) = result {
+            warn!("[coord] failed to persist lease state: {:#}", e);
+        }
+    }
+}
+
+/// Body of the lease requests: who is asking.
+#[derive(Debug, Deserialize)]
+struct Caller {
+    rank: usize,
+    pid: u32,
+}
+
+/// Body of a renewal: the caller and the training step it reached.
+#[derive(Debug, Deserialize)]
+struct Renewal {
+    #[serde(flatten)]
+    caller: Caller,
+    step: u64,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum LeaseReply {
+    Leased {
+        #[serde(flatten)]
+        lease: Lease,
+        /// Absolute path of the shard file.
+        path: String,
+        timeout_secs: u64,
+    },
+    Wait {
+        retry_after_secs: f64,
+    },
+    Done,
+}
+
+enum LeaseRejection {
+    Caller(Rejection),
+    Lease(LeaseError),
+    Disabled,
+}
+
+impl IntoResponse for LeaseRejection {
+    fn into_response(self) -> Response {
+        match self {
+            LeaseRejection::Caller(r @ Rejection::UnknownRank(_)) => (StatusCode::NOT_FOUND, r.to_string()),
+            LeaseRejection::Caller(r @ Rejection::StalePid { .. }) => (StatusCode::CONFLICT, r.to_string()),
+            LeaseRejection::Lease(e) => (StatusCode::CONFLICT, e.to_string()),
+            LeaseRejection::Disabled => (StatusCode::NOT_FOUND, "shard leases are disabled".to_string()),
+        }
+        .into_response()
+    }
+}
+
+/// Worker-facing lease endpoints, served next to `/heartbeat`:
+///
+/// - `POST /lease`: get the rank's current or next work item
+/// - `POST /lease/{id}/renew`: report training progress on an item, with
+///   the current `step`
+/// - `POST /lease/{id}/complete`: report an item processed
+///
+/// All take `{"rank": ..., "pid": ...}` and reject callers that are not
+/// the rank's current process. Renewal and completion of a lease the rank
+/// no longer holds are rejected with 409.
+pub fn routes() -> Router<Arc<Shared>> {
+    Router::new()
+        .route("/lease", post(lease))
+        .route("/lease/{id}/renew", post(renew))
+        .route("/lease/{id}/complete", post(complete))
+}
+
+fn authorize(shared: &Shared, caller: &Caller) -> Result<(), LeaseRejection> {
+    heartbeat::check_sender(&shared.workers(), caller.rank, caller.pid).map_err(LeaseRejection::Caller)
+}
+
+async fn lease(
+    State(shared): State<Arc<Shared>>,
+    Json(caller): Json<Caller>,
+) -> Result<Json<LeaseReply>, LeaseRejection> {
+    authorize(&shared, &caller)?;
+    let queue = shared.leases.as_ref().ok_or(LeaseRejection::Disabled)?;
+    let grant = queue.lock().unwrap_or_else(|e| e.into_inner()).acquire(caller.rank, now_secs());
+    let reply = match grant {
+        Grant::Lease(lease) => {
+            shared.journal.record(Event::LeaseGranted {
+                rank: lease.rank,
+                lease_id: lease.lease_id,
+                epoch: lease.epoch,
+                item: lease.item.clone(),
+            });
+            let path = Path::new(&shared.config.dataset_dir).join(&lease.item.shard);
+            LeaseReply::Leased {
+                path: std::path::absolute(&path).unwrap_or(path).display().to_string(),
+                lease,
+                timeout_secs: shared.config.lease_timeout,
+            }
+        }
+        Grant::Wait => LeaseReply::Wait { retry_after_secs: 1.0 },
+        Grant::Done => LeaseReply::Done,
+    };
+    Ok(Json(reply))
+}
+
+async fn renew(
+    State(shared): State<Arc<Shared>>,
+    UrlPath(lease_id): UrlPath<u64>,
+    Json(renewal): Json<Renewal>,
+) -> Result<StatusCode, LeaseRejection> {
+    let Renewal { caller, step } = renewal;
+    authorize(&shared, &caller)?;
+    let queue = shared.leases.as_ref().ok_or(LeaseRejection::Disabled)?;
+    queue
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .renew(caller.rank, lease_id, step, now_secs())
+        .map_err(LeaseRejection::Lease)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn complete(
+    State(shared): State<Arc<Shared>>,
+    UrlPath(lease_id): UrlPath<u64>,
+    Json(caller): Json<Caller>,
+) -> Result<StatusCode, LeaseRejection> {
+    authorize(&shared, &caller)?;
+    let queue = shared.leases.as_ref().ok_or(LeaseRejection::Disabled)?;
+    let (lease, finished, progress) = {
+        let mut queue = queue.lock().unwrap_or_else(|e| e.into_inner());
+        let (lease, finished) = queue.complete(caller.rank, lease_id).map_err(LeaseRejection::Lease)?;
+        (lease, finished, queue.progress())
+    };
+    shared.journal.record(Event::LeaseCompleted {
+        rank: lease.rank,
+        lease_id: lease.lease_id,
+        epoch: lease.epoch,
+        item: lease.item,
+    });
+    if let Some(epoch) = finished {
+        info!("[coord] epoch {}/{} complete", epoch + 1, progress.epochs);
+        shared.journal.record(Event::EpochCompleted { epoch });
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shards::{Shard, MANIFEST_VERSION};
+    use tempfile::TempDir;
+
+    /// A queue over two shards of 10 lines, in items of 5 lines, with a
+    /// lease timeout of 30 seconds. It saves into the returned directory.
+    fn queue(epochs: usize) -> (TempDir, LeaseQueue) {
+        let shards = (0..2)
+            .map(|i| Shard { name: format!("shard_{}", i), bytes: 100, lines: 10, sha256: String::new() })
+            .collect();
+        let manifest = ShardManifest {
+            version: MANIFEST_VERSION,
+            job_id: "job".to_string(),
+            generation: 0,
+            world_size: 2,
+            created_at: 0.0,
+            shards,
+            offsets: BTreeMap::new(),
+            assignment: BTreeMap::new(),
+        };
+        let config = Config { lease_lines: 5, lease_timeout: 30, epochs, ..Config::default() };
+        let dir = tempfile::tempdir().unwrap();
+        let queue = LeaseQueue::new(dir.path().join("leases.json"), &manifest, &config);
+        (dir, queue)
+    }
+
+    fn lease(grant: Grant) -> Lease {
+        match grant {
+            Grant::Lease(lease) => lease,
+            other => panic!("expected a lease, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn acquire_hands_out_items_in_order_one_per_rank() {
+        let (_dir, mut q) = queue(1);
+        let a = lease(q.acquire(0, 0.0));
+        let b = lease(q.acquire(1, 0.0));
+        assert_eq!((a.item.shard.as_str(), a.item.start_line, a.item.end_line), ("shard_0", 0, 5));
+        assert_eq!((b.item.shard.as_str(), b.item.start_line), ("shard_0", 5));
+        // A rank asking again gets the lease it holds.
+        assert_eq!(lease(q.acquire(0, 1.0)).lease_id, a.lease_id);
+        assert_eq!(q.progress().leased, 2);
+    }
+
+    #[test]
+    fn completing_every_item_finishes_the_epochs() {
+        let (_dir, mut q) = queue(2);
+        for epoch in 0..2 {
+            for i in 0..4 {
+                let l = lease(q.acquire(0, 0.0));
+                let (_, finished) = q.complete(0, l.lease_id).unwrap();
+                assert_eq!(finished, (i == 3).then_some(epoch));
+            }
+        }
+        assert!(matches!(q.acquire(0, 0.0), Grant::Done));
+        assert_eq!(q.progress().epoch, 2);
+    }
+
+    #[test]
+    fn only_the_holder_completes_a_lease() {
+        let (_dir, mut q) = queue(1);
+        let l = lease(q.acquire(0, 0.0));
+        let refused = q.complete(1, l.lease_id).unwrap_err();
+        assert_eq!(refused, LeaseError::NotHeld { lease_id: l.lease_id, rank: 1 });
+        q.complete(0, l.lease_id).unwrap();
+        assert!(q.complete(0, l.lease_id).is_err());
+    }
+
+    #[test]
+    fn rank_waits_while_the_rest_is_leased() {
+        let (_dir, mut q) = queue(1);
+        for rank in 0..4 {
+            lease(q.acquire(rank, 0.0));
+        }
+        assert!(matches!(q.acquire(4, 0.0), Grant::Wait));
+    }
+
+    #[test]
+    fn expired_lease_goes_back_to_the_front() {
+        let (_dir, mut q) = queue(1);
+        let stale = lease(q.acquire(0, 0.0));
+        lease(q.acquire(1, 10.0));
+        let expired = q.expire(30.0);
+        assert_eq!(expired.iter().map(|l| l.lease_id).collect::<Vec<_>>(), [stale.lease_id]);
+
+        let next = lease(q.acquire(2, 30.0));
+        assert_eq!(next.item, stale.item);
+        assert!(q.complete(0, stale.lease_id).is_err());
+    }
+
+    #[test]
+    fn renew_extends_the_lease_only_when_the_step_advances() {
+        let (_dir, mut q) = queue(1);
+        let l = lease(q.acquire(0, 0.0));
+        assert_eq!(q.renew(0, l.lease_id, 3, 20.0), Ok(true));
+        assert_eq!(q.renew(0, l.lease_id, 3, 40.0), Ok(false));
+        // Renewed at 20, so it outlives the original deadline of 30.
+        assert!(q.expire(45.0).is_empty());
+        assert_eq!(q.expire(50.0).len(), 1);
+        assert!(q.renew(0, l.lease_id, 4, 50.0).is_err());
+    }
+
+    #[test]
+    fn revoked_item_goes_to_another_rank() {
+        let (_dir, mut q) = queue(1);
+        let slow = lease(q.acquire(0, 0.0));
+        lease(q.acquire(1, 0.0));
+        assert_eq!(q.revoke(0).map(|l| l.lease_id), Some(slow.lease_id));
+        assert!(q.revoke(0).is_none());
+        assert!(q.complete(0, slow.lease_id).is_err());
+
+        // The straggler moves on to the next item; another rank takes over.
+        assert_ne!(lease(q.acquire(0, 1.0)).item, slow.item);
+        assert_eq!(lease(q.acquire(2, 1.0)).item, slow.item);
+    }
+}
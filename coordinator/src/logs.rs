@@ -0,0 +1,172 @@
+use crate::config::Config;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::Child;
+use tracing::warn;
+
+/// `log_dir/<job>/worker_<rank>.log`.
+pub fn worker_log_path(log_dir: &Path, job_id: &str, rank: usize) -> PathBuf {
+    log_dir.join(job_id).join(format!("worker_{}.log", rank))
+}
+
+/// Append-only log file that rolls over to `<name>.1`, `<name>.2`, ...
+/// once it would grow past `max_bytes`, keeping at most `max_files` of them.
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_bytes: u64,
+    max_files: usize,
+}
+
+impl RotatingFile {
+    pub fn open(path: PathBuf, max_bytes: u64, max_files: usize) -> io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            file,
+            size,
+            max_bytes,
+            max_files,
+        })
+    }
+
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.max_bytes > 0 && self.size > 0 && self.size + len > self.max_bytes {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.file.write_all(b"\n")?;
+        self.size += len;
+        Ok(())
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut name = self.path.as_os_str().to_owned();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.max_files == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            // Renaming onto `.max_files` drops the oldest file.
+            for n in (1..self.max_files).rev() {
+                let from = self.rotated(n);
+                if from.exists() {
+                    std::fs::rename(&from, self.rotated(n + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, self.rotated(1))?;
+        }
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+/// Captures worker stdout/stderr into one rotating file per rank, each line
+/// prefixed with a timestamp, rank, attempt, and pid. Disabled when
+/// `log_dir` is empty, in which case workers inherit the coordinator's stdio.
+pub struct WorkerLogs {
+    dir: Option<PathBuf>,
+    job_id: String,
+    max_bytes: u64,
+    max_files: usize,
+    forward: bool,
+    /// A rank's file stays open across attempts.
+    files: Mutex<HashMap<usize, Arc<Mutex<RotatingFile>>>>,
+}
+
+impl WorkerLogs {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            dir: (!config.log_dir.is_empty()).then(|| PathBuf::from(&config.log_dir)),
+            job_id: config.job_id.clone(),
+            max_bytes: config.log_max_bytes,
+            max_files: config.log_max_files,
+            forward: config.log_forward,
+            files: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether workers are spawned with their output piped to us.
+    pub fn enabled(&self) -> bool {
+        self.dir.is_some()
+    }
+
+    fn file(&self, dir: &Path, rank: usize) -> Result<Arc<Mutex<RotatingFile>>> {
+        let mut files = self.files.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(file) = files.get(&rank) {
+            return Ok(file.clone());
+        }
+        let path = worker_log_path(dir, &self.job_id, rank);
+        let file = RotatingFile::open(path.clone(), self.max_bytes, self.max_files)
+            .with_context(|| format!("failed to open worker log {}", path.display()))?;
+        let file = Arc::new(Mutex::new(file));
+        files.insert(rank, file.clone());
+        Ok(file)
+    }
+
+    /// Takes over the piped stdout/stderr of a freshly spawned worker.
+    /// No-op if capture is disabled.
+    pub fn attach(&self, child: &mut Child, rank: usize, attempt: usize) -> Result<()> {
+        let Some(dir) = &self.dir else {
+            return Ok(());
+        };
+        let file = self.file(dir, rank)?;
+        let pid = child.id().unwrap_or(0);
+        if let Some(stdout) = child.stdout.take() {
+            let prefix = format!("[rank={} attempt={} pid={}]", rank, attempt, pid);
+            tokio::spawn(pump(stdout, prefix, file.clone(), self.forward));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            let prefix = format!("[rank={} attempt={} pid={} stderr]", rank, attempt, pid);
+            tokio::spawn(pump(stderr, prefix, file, self.forward));
+        }
+        Ok(())
+    }
+}
+
+/// Copies lines from a worker pipe into its log until the pipe closes.
+/// Keeps draining after write errors so the worker never blocks on a full pipe.
+async fn pump<R: AsyncRead + Unpin>(stream: R, prefix: String, file: Arc<Mutex<RotatingFile>>, forward: bool) {
+    let mut reader = BufReader::new(stream);
+    let mut buf = Vec::new();
+    let mut write_failed = false;
+    loop {
+        buf.clear();
+        match reader.read_until(b'\n', &mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+        let text = String::from_utf8_lossy(&buf);
+        let line = format!(
+            "{} {} {}",
+            chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ"),
+            prefix,
+            text.trim_end_matches(['\n', '\r'])
+        );
+        let result = file.lock().unwrap_or_else(|e| e.into_inner()).write_line(&line);
+        if let Err(e) = result {
+            if !write_failed {
+                warn!("[coord] failed to write worker log: {}", e);
+                write_failed = true;
+            }
+        }
+        if forward {
+            let _ = writeln!(io::stdout().lock(), "{}", line);
+        }
+    }
+}
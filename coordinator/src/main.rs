@@ -1,265 +1,443 @@
+mod api;
+mod barrier;
+mod checkpoint;
+mod config;
+mod coordinator;
+mod events;
+mod heartbeat;
+mod launcher;
+mod leases;
+mod logs;
+mod metrics;
+mod restart;
+mod retention;
+mod scheduler;
+mod shards;
+mod shutdown;
+mod signals;
+mod state;
+mod straggler;
+mod summary;
+mod worker;
+
 use anyhow::Result;
-use clap::Parser;
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use clap::{Parser, Subcommand};
+use config::ResolvedConfig;
+use coordinator::Coordinator;
+use serde::Serialize;
 use std::path::{Path, PathBuf};
-use std::process::Stdio;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use tokio::process::Command;
-use tokio::time::sleep;
-use tracing::{error, info, warn};
+use std::process::ExitCode;
 
 #[derive(Parser)]
 #[command(name = "Distributed Training Coordinator")]
 #[command(about = "Manages worker processes, heartbeats, and recovery")]
 struct Args {
+    /// Job file (TOML or YAML); overrides defaults, overridden by env vars and flags
+    #[arg(long, env = "COORDINATOR_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Print the resolved configuration and where each value came from, then exit
+    #[arg(long)]
+    print_config: bool,
+
+    #[command(flatten)]
+    overrides: ConfigArgs,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print a job's event journal
+    Events(EventsArgs),
+    /// Inspect a job's checkpoints and roll them back
+    #[command(subcommand)]
+    Ckpt(CkptCommand),
+    /// Run many jobs within a host-wide worker slot budget, taking job
+    /// submissions over HTTP
+    Daemon,
+    /// Print a job's progress, throughput and ETA from its summary.json
+    Status {
+        /// Job to read; defaults to the configured job ID
+        job: Option<String>,
+
+        /// Print the raw summary.json
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum CkptCommand {
+    /// List each rank's checkpoints
+    Ls {
+        /// Job to read; defaults to the configured job ID
+        job: Option<String>,
+
+        /// Only this rank (repeatable)
+        #[arg(long)]
+        rank: Vec<usize>,
+    },
+    /// Pretty-print a checkpoint's state.json
+    Show {
+        /// Job to read; defaults to the configured job ID
+        job: Option<String>,
+
+        #[arg(long)]
+        rank: usize,
+
+        /// Step to show; defaults to the one LATEST points to
+        #[arg(long)]
+        step: Option<u64>,
+    },
+    /// Compare the state.json of two steps of a rank
+    Diff {
+        /// Job to read; defaults to the configured job ID
+        job: Option<String>,
+
+        #[arg(long)]
+        rank: usize,
+
+        #[arg(long)]
+        from: u64,
+
+        #[arg(long)]
+        to: u64,
+    },
+    /// Move LATEST of one rank, or of every rank, back to a chosen step
+    Rollback {
+        /// Job to change; defaults to the configured job ID
+        job: Option<String>,
+
+        /// Only this rank (repeatable); defaults to every rank
+        #[arg(long)]
+        rank: Vec<usize>,
+
+        /// Step to roll back to
+        #[arg(long, required_unless_present = "global", conflicts_with = "global")]
+        step: Option<u64>,
+
+        /// Roll back to each rank's step in GLOBAL_LATEST
+        #[arg(long)]
+        global: bool,
+    },
+}
+
+#[derive(clap::Args)]
+struct EventsArgs {
+    /// Job to read; defaults to the configured job ID
+    job: Option<String>,
+
+    /// Only events for this rank (repeatable)
+    #[arg(long)]
+    rank: Vec<usize>,
+
+    /// Only events of this type, e.g. worker_failed (repeatable)
+    #[arg(long = "event")]
+    events: Vec<String>,
+
+    /// Only events at or after this Unix time
+    #[arg(long)]
+    since: Option<f64>,
+
+    /// Print only the last N matching events
+    #[arg(short = 'n', long)]
+    lines: Option<usize>,
+
+    /// Keep printing new events as they are written
+    #[arg(short, long)]
+    follow: bool,
+}
+
+/// CLI overrides for [`config::Config`]; unset flags fall through to the lower layers.
+#[derive(clap::Args, Serialize)]
+struct ConfigArgs {
     /// Job ID
-    #[arg(long, default_value = "demo-job")]
-    job_id: String,
+    #[arg(long)]
+    job_id: Option<String>,
 
     /// Number of workers
-    #[arg(long, default_value = "4")]
-    world_size: usize,
+    #[arg(long)]
+    world_size: Option<usize>,
 
     /// Checkpoint directory
-    #[arg(long, default_value = "./checkpoints")]
-    checkpoint_dir: String,
+    #[arg(long)]
+    checkpoint_dir: Option<String>,
 
     /// Checkpoint every N steps
-    #[arg(long, default_value = "5")]
-    checkpoint_every: usize,
+    #[arg(long)]
+    checkpoint_every: Option<usize>,
 
     /// Sleep between steps (seconds)
-    #[arg(long, default_value = "0.5")]
-    sleep_sec: f64,
+    #[arg(long)]
+    sleep_sec: Option<f64>,
 
     /// Dataset directory
-    #[arg(long, default_value = "./data/shards")]
-    dataset_dir: String,
+    #[arg(long)]
+    dataset_dir: Option<String>,
 
     /// Heartbeat timeout (seconds)
-    #[arg(long, default_value = "10")]
-    heartbeat_timeout: u64,
+    #[arg(long)]
+    heartbeat_timeout: Option<u64>,
 
-    /// Max restarts per worker
-    #[arg(long, default_value = "50")]
-    max_restarts: usize,
+    /// Seconds without step progress before a worker counts as hung (0 = disabled)
+    #[arg(long)]
+    progress_timeout: Option<u64>,
 
-    /// Enable S3
-    #[arg(long, default_value = "false")]
-    use_s3: bool,
-}
+    /// Signal asking a hung worker for a stack dump before it is killed (e.g. SIGUSR1)
+    #[arg(long)]
+    hang_dump_signal: Option<String>,
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct WorkerHeartbeat {
-    timestamp: f64,
-    rank: usize,
-    pid: u32,
-}
+    /// Seconds to wait after the stack dump signal before killing a hung worker
+    #[arg(long)]
+    hang_dump_grace: Option<u64>,
 
-#[derive(Debug)]
-struct Worker {
-    rank: usize,
-    child: tokio::process::Child,
-    restarts: usize,
-    last_heartbeat: f64,
-}
+    /// Flag ranks slower than this fraction of the median step rate (0 = disabled)
+    #[arg(long)]
+    straggler_threshold: Option<f64>,
 
-struct Coordinator {
-    job_id: String,
-    world_size: usize,
-    checkpoint_dir: PathBuf,
-    dataset_dir: PathBuf,
-    workers: HashMap<usize, Worker>,
-    max_restarts: usize,
-    heartbeat_timeout: Duration,
-    args: Args,
-}
+    /// Seconds a rank must stay slow before the straggler policy applies
+    #[arg(long)]
+    straggler_grace: Option<u64>,
 
-impl Coordinator {
-    fn new(args: Args) -> Self {
-        Self {
-            job_id: args.job_id.clone(),
-            world_size: args.world_size,
-            checkpoint_dir: PathBuf::from(&args.checkpoint_dir),
-            dataset_dir: PathBuf::from(&args.dataset_dir),
-            workers: HashMap::new(),
-            max_restarts: args.max_restarts,
-            heartbeat_timeout: Duration::from_secs(args.heartbeat_timeout),
-            args,
-        }
-    }
+    /// What to do about stragglers: log, restart, or reshard
+    #[arg(long)]
+    straggler_policy: Option<String>,
 
-    async fn spawn_worker(&self, rank: usize) -> Result<tokio::process::Child> {
-        let env_vars = vec![
-            ("JOB_ID", self.job_id.clone()),
-            ("RANK", rank.to_string()),
-            ("WORLD_SIZE", self.world_size.to_string()),
-            ("CHECKPOINT_DIR", self.checkpoint_dir.to_string_lossy().to_string()),
-            ("CHECKPOINT_EVERY", self.args.checkpoint_every.to_string()),
-            ("SLEEP_SEC", self.args.sleep_sec.to_string()),
-            ("DATASET_DIR", self.dataset_dir.to_string_lossy().to_string()),
-            ("USE_S3", if self.args.use_s3 { "1" } else { "0" }.to_string()),
-        ];
-
-        let mut cmd = Command::new("python");
-        cmd.arg("demo/worker.py");
-        cmd.current_dir("."); // Project root
-
-        for (k, v) in env_vars {
-            cmd.env(k, v);
-        }
+    /// Bind address for the control API
+    #[arg(long)]
+    api_bind: Option<String>,
 
-        cmd.stdout(Stdio::inherit()).stderr(Stdio::inherit());
+    /// Control API port (0 = disabled)
+    #[arg(long)]
+    api_port: Option<u16>,
 
-        let child = cmd.spawn()?;
-        info!("[coord] spawned worker rank={} pid={}", rank, child.id().unwrap_or(0));
+    /// Job submission API port of the scheduler daemon
+    #[arg(long)]
+    scheduler_port: Option<u16>,
 
-        Ok(child)
-    }
+    /// Worker slots the scheduler daemon runs at once (0 = number of CPUs)
+    #[arg(long)]
+    scheduler_slots: Option<usize>,
 
-    async fn check_heartbeat(&self, rank: usize) -> bool {
-        let heartbeat_file = self
-            .checkpoint_dir
-            .join(&self.job_id)
-            .join(format!("worker_{}", rank))
-            .join("HEARTBEAT");
+    /// Preempt lower-priority jobs to admit a job that does not fit
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    scheduler_preemption: Option<bool>,
 
-        if !heartbeat_file.exists() {
-            return false;
-        }
+    /// Seconds workers get to checkpoint and exit after SIGTERM on shutdown
+    #[arg(long)]
+    shutdown_grace: Option<u64>,
 
-        match std::fs::read_to_string(&heartbeat_file) {
-            Ok(content) => {
-                if let Ok(hb) = serde_json::from_str::<WorkerHeartbeat>(&content) {
-                    let now = SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .unwrap_or_default()
-                        .as_secs_f64();
-                    (now - hb.timestamp) < self.heartbeat_timeout.as_secs_f64()
-                } else {
-                    false
-                }
-            }
-            Err(_) => false,
-        }
-    }
+    /// Directory for worker logs; empty = workers inherit the coordinator's stdio
+    #[arg(long)]
+    log_dir: Option<String>,
 
-    async fn monitor_workers(&mut self) {
-        loop {
-            sleep(Duration::from_millis(500)).await;
-
-            let mut to_restart = Vec::new();
-
-            for (rank, worker) in self.workers.iter_mut() {
-                match worker.child.try_wait() {
-                    Ok(Some(status)) => {
-                        if status.success() {
-                            info!("[coord] worker rank={} completed (exit 0)", rank);
-                            to_restart.push(*rank);
-                        } else {
-                            warn!("[coord] worker rank={} exited with status {}", rank, status);
-                            to_restart.push(*rank);
-                        }
-                    }
-                    Ok(None) => {
-                        // Still running; check heartbeat
-                        if !self.check_heartbeat(*rank).await {
-                            warn!("[coord] worker rank={} heartbeat timeout!", rank);
-                            let _ = worker.child.kill().await;
-                            to_restart.push(*rank);
-                        }
-                    }
-                    Err(e) => {
-                        error!("[coord] failed to check worker {}: {}", rank, e);
-                        to_restart.push(*rank);
-                    }
-                }
-            }
+    /// Rotate a worker log at this size in bytes (0 = never)
+    #[arg(long)]
+    log_max_bytes: Option<u64>,
 
-            for rank in to_restart {
-                if let Some(mut w) = self.workers.remove(&rank) {
-                    if w.restarts >= self.max_restarts {
-                        warn!("[coord] rank={} max restarts hit; not restarting", rank);
-                        continue;
-                    }
-
-                    w.restarts += 1;
-                    info!("[coord] restarting rank={} (attempt {}/{})", rank, w.restarts, self.max_restarts);
-
-                    sleep(Duration::from_millis(500)).await;
-
-                    match self.spawn_worker(rank).await {
-                        Ok(child) => {
-                            let now = SystemTime::now()
-                                .duration_since(UNIX_EPOCH)
-                                .unwrap_or_default()
-                                .as_secs_f64();
-                            self.workers.insert(
-                                rank,
-                                Worker {
-                                    rank,
-                                    child,
-                                    restarts: w.restarts,
-                                    last_heartbeat: now,
-                                },
-                            );
-                        }
-                        Err(e) => {
-                            error!("[coord] failed to restart rank={}: {}", rank, e);
-                        }
-                    }
-                }
-            }
+    /// Rotated worker logs kept per rank
+    #[arg(long)]
+    log_max_files: Option<usize>,
 
-            if self.workers.is_empty() {
-                info!("[coord] all workers done. job completed.");
-                break;
-            }
-        }
-    }
+    /// Echo captured worker output on the coordinator's stdout
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    log_forward: Option<bool>,
 
-    async fn run(&mut self) -> Result<()> {
-        info!("[coord] job={}", self.job_id);
-        info!("[coord] world_size={}", self.world_size);
-        info!("[coord] checkpoints={}", self.checkpoint_dir.display());
-
-        // Spawn all workers
-        for rank in 0..self.world_size {
-            let child = self.spawn_worker(rank).await?;
-            let now = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap_or_default()
-                .as_secs_f64();
-            self.workers.insert(
-                rank,
-                Worker {
-                    rank,
-                    child,
-                    restarts: 0,
-                    last_heartbeat: now,
-                },
-            );
-        }
+    /// Resume from the state a previous coordinator of this job left behind
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    resume: Option<bool>,
 
-        info!("[coord] all workers spawned");
+    /// Workers left running by a previous coordinator: adopt or kill
+    #[arg(long)]
+    orphan_policy: Option<String>,
 
-        // Monitor workers
-        self.monitor_workers().await;
+    /// Assign dataset shards to ranks through a manifest in the job directory
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    shard_manifest: Option<bool>,
 
-        info!("[coord] coordinator shutdown");
-        Ok(())
-    }
+    /// Lease shards to ranks from a work-stealing queue (needs --heartbeat-http-port)
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    shard_leases: Option<bool>,
+
+    /// Seconds a shard lease survives without training progress on it
+    #[arg(long)]
+    lease_timeout: Option<u64>,
+
+    /// Lines per leased work item (0 = whole shards)
+    #[arg(long)]
+    lease_lines: Option<u64>,
+
+    /// Passes over the dataset when shards are leased
+    #[arg(long)]
+    epochs: Option<usize>,
+
+    /// Seconds between global checkpoint barriers (0 = only on request)
+    #[arg(long)]
+    global_checkpoint_interval: Option<u64>,
+
+    /// Seconds every rank gets to commit its checkpoint for a barrier
+    #[arg(long)]
+    barrier_timeout: Option<u64>,
+
+    /// Verify and repair a rank's LATEST checkpoint before launching it
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    verify_checkpoints: Option<bool>,
+
+    /// Seconds between checkpoint garbage collection passes (0 = keep everything)
+    #[arg(long)]
+    checkpoint_gc_interval: Option<u64>,
+
+    /// Newest checkpoints each rank keeps
+    #[arg(long)]
+    checkpoint_keep_last: Option<usize>,
+
+    /// Also keep checkpoints whose step is a multiple of this (0 = none)
+    #[arg(long)]
+    checkpoint_keep_every: Option<u64>,
+
+    /// Seconds between writes of summary.json (0 = disabled)
+    #[arg(long)]
+    summary_interval: Option<u64>,
+
+    /// Poll HEARTBEAT files on the shared checkpoint filesystem
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    heartbeat_files: Option<bool>,
+
+    /// Bind address for the heartbeat listeners
+    #[arg(long)]
+    heartbeat_bind: Option<String>,
+
+    /// HTTP heartbeat listener port (0 = disabled)
+    #[arg(long)]
+    heartbeat_http_port: Option<u16>,
+
+    /// UDP heartbeat listener port (0 = disabled)
+    #[arg(long)]
+    heartbeat_udp_port: Option<u16>,
+
+    /// Host workers use to reach the heartbeat listeners
+    #[arg(long)]
+    heartbeat_advertise_host: Option<String>,
+
+    /// Max restarts per worker
+    #[arg(long)]
+    max_restarts: Option<usize>,
+
+    /// First restart delay (milliseconds)
+    #[arg(long)]
+    restart_backoff_initial_ms: Option<u64>,
+
+    /// Maximum restart delay (milliseconds)
+    #[arg(long)]
+    restart_backoff_max_ms: Option<u64>,
+
+    /// Restart delay growth factor per consecutive failure
+    #[arg(long)]
+    restart_backoff_multiplier: Option<f64>,
+
+    /// Restart delay jitter as a fraction of the delay (0.0-1.0)
+    #[arg(long)]
+    restart_backoff_jitter: Option<f64>,
+
+    /// Healthy uptime (seconds) that resets a worker's backoff
+    #[arg(long)]
+    restart_reset_after: Option<u64>,
+
+    /// Crash exits within the crash-loop window before giving up on a worker (0 = off)
+    #[arg(long)]
+    crash_loop_max_failures: Option<usize>,
+
+    /// Crash-loop detection window (seconds)
+    #[arg(long)]
+    crash_loop_window: Option<u64>,
+
+    /// Enable S3
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    use_s3: Option<bool>,
+
+    /// Worker executable
+    #[arg(long)]
+    worker_program: Option<String>,
+
+    /// Worker argument (repeatable); supports {rank}, {world_size}, {job_id}
+    #[arg(long = "worker-arg", allow_hyphen_values = true)]
+    #[serde(rename = "worker_args")]
+    worker_args: Option<Vec<String>>,
+
+    /// Worker working directory
+    #[arg(long)]
+    worker_cwd: Option<String>,
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> Result<ExitCode> {
     tracing_subscriber::fmt::init();
 
     let args = Args::parse();
-    let mut coordinator = Coordinator::new(args);
+    let resolved = ResolvedConfig::load(args.config.as_deref(), serde_json::to_value(&args.overrides)?)?;
+
+    if args.print_config {
+        print!("{}", resolved.render()?);
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let config = &resolved.config;
+    let checkpoint_dir = Path::new(&config.checkpoint_dir);
+    match args.command {
+        Some(Command::Events(cmd)) => {
+            let job = cmd.job.as_deref().unwrap_or(&config.job_id);
+            let path = events::journal_path(checkpoint_dir, job);
+            let filter = events::Filter {
+                ranks: cmd.rank,
+                events: cmd.events,
+                since: cmd.since,
+            };
+            events::tail(&path, &filter, cmd.lines, cmd.follow).await?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Ckpt(cmd)) => {
+            let job = |job: Option<String>| job.unwrap_or_else(|| config.job_id.clone());
+            match cmd {
+                CkptCommand::Ls { job: j, rank } => checkpoint::print_list(checkpoint_dir, &job(j), &rank)?,
+                CkptCommand::Show { job: j, rank, step } => {
+                    checkpoint::print_state(checkpoint_dir, &job(j), rank, step)?
+                }
+                CkptCommand::Diff { job: j, rank, from, to } => {
+                    checkpoint::print_diff(checkpoint_dir, &job(j), rank, from, to)?
+                }
+                CkptCommand::Rollback { job: j, rank, step, global } => {
+                    let target = match step {
+                        Some(step) if !global => checkpoint::RollbackTarget::Step(step),
+                        _ => checkpoint::RollbackTarget::Global,
+                    };
+                    checkpoint::rollback(checkpoint_dir, &job(j), &rank, target)?
+                }
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Daemon) => {
+            let scheduler = scheduler::Scheduler::open(
+                args.config.clone(),
+                serde_json::to_value(&args.overrides)?,
+                config,
+            )
+            .await?;
+            scheduler.run(&config.api_bind, config.scheduler_port).await?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Status { job, json }) => {
+            let job = job.as_deref().unwrap_or(&config.job_id);
+            summary::print(checkpoint_dir, job, json)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        None => {}
+    }
+
+    let mut coordinator = Coordinator::new(resolved)?;
 
-    coordinator.run().await?;
+    let outcome = coordinator.run().await?;
 
-    Ok(())
+    Ok(outcome.exit_code())
 }
@@ -0,0 +1,252 @@
+use crate::coordinator::{now_secs, Shared};
+use crate::worker::WorkerState;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::Ordering;
+
+/// Upper bounds (seconds) of the worker lifetime histogram buckets.
+const LIFETIME_BUCKETS: [f64; 10] = [
+    1.0, 5.0, 15.0, 30.0, 60.0, 300.0, 900.0, 1800.0, 3600.0, 14400.0,
+];
+
+const STATES: [WorkerState; 7] = [
+    WorkerState::Pending,
+    WorkerState::Running,
+    WorkerState::Succeeded,
+    WorkerState::Failed,
+    WorkerState::Exhausted,
+    WorkerState::Draining,
+    WorkerState::Stopped,
+];
+
+/// Cumulative histogram of how long worker processes ran.
+#[derive(Debug, Clone, Default)]
+pub struct LifetimeHistogram {
+    buckets: [u64; LIFETIME_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl LifetimeHistogram {
+    pub fn observe(&mut self, secs: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(LIFETIME_BUCKETS) {
+            if secs <= bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += secs;
+    }
+
+    fn merge(&mut self, other: &LifetimeHistogram) {
+        for (a, b) in self.buckets.iter_mut().zip(other.buckets) {
+            *a += b;
+        }
+        self.count += other.count;
+        self.sum += other.sum;
+    }
+}
+
+/// Renders the job's metrics in the Prometheus text exposition format.
+/// Everything is derived from the worker map at scrape time.
+pub fn render(shared: &Shared) -> String {
+    let now = now_secs();
+    let job = escape(&shared.config.job_id);
+    let workers = shared.workers();
+    let mut ranks: Vec<_> = workers.values().collect();
+    ranks.sort_by_key(|w| w.rank);
+
+    let mut out = String::new();
+
+    let name = header(
+        &mut out,
+        "coordinator_uptime_seconds",
+        "gauge",
+        "Seconds since the coordinator started the job.",
+    );
+    let _ = writeln!(
+        out,
+        "{}{{job=\"{}\"}} {}",
+        name,
+        job,
+        now - shared.started_at
+    );
+
+    let name = header(
+        &mut out,
+        "coordinator_generation",
+        "gauge",
+        "Number of world size changes so far.",
+    );
+    let _ = writeln!(out, "{}{{job=\"{}\"}} {}", name, job, shared.generation().number);
+
+    let name = header(
+        &mut out,
+        "coordinator_workers",
+        "gauge",
+        "Number of ranks in each lifecycle state.",
+    );
+    let mut per_state: BTreeMap<String, usize> =
+        STATES.iter().map(|s| (s.to_string(), 0)).collect();
+    for w in &ranks {
+        *per_state.entry(w.state.to_string()).or_default() += 1;
+    }
+    for (state, n) in per_state {
+        let _ = writeln!(out, "{}{{job=\"{}\",state=\"{}\"}} {}", name, job, state, n);
+    }
+
+    let name = header(
+        &mut out,
+        "coordinator_worker_restarts_total",
+        "counter",
+        "Restarts per rank, by the failure that caused them.",
+    );
+    for w in &ranks {
+        for (reason, n) in &w.restart_reasons {
+            let _ = writeln!(
+                out,
+                "{}{{job=\"{}\",rank=\"{}\",reason=\"{}\"}} {}",
+                name, job, w.rank, reason, n
+            );
+        }
+    }
+
+    let name = header(
+        &mut out,
+        "coordinator_worker_heartbeat_age_seconds",
+        "gauge",
+        "Seconds since the current process of each rank last heartbeated.",
+    );
+    for w in &ranks {
+        if let Some(age) = w.status(now).heartbeat_age_secs {
+            let _ = writeln!(
+                out,
+                "{}{{job=\"{}\",rank=\"{}\"}} {}",
+                name, job, w.rank, age
+            );
+        }
+    }
+
+    let name = header(
+        &mut out,
+        "coordinator_worker_step",
+        "gauge",
+        "Last training step reported by each rank.",
+    );
+    for w in &ranks {
+        if let Some(step) = w.progress.as_ref().and_then(|p| p.step) {
+            let _ = writeln!(
+                out,
+                "{}{{job=\"{}\",rank=\"{}\"}} {}",
+                name, job, w.rank, step
+            );
+        }
+    }
+
+    let name = header(
+        &mut out,
+        "coordinator_worker_checkpoint_step",
+        "gauge",
+        "Step of the last checkpoint reported by each rank.",
+    );
+    for w in &ranks {
+        if let Some(step) = w.progress.as_ref().and_then(|p| p.last_checkpoint_step) {
+            let _ = writeln!(
+                out,
+                "{}{{job=\"{}\",rank=\"{}\"}} {}",
+                name, job, w.rank, step
+            );
+        }
+    }
+
+    let name = header(
+        &mut out,
+        "coordinator_worker_lifetime_seconds",
+        "histogram",
+        "How long worker processes ran before exiting or being killed.",
+    );
+    let mut lifetimes = LifetimeHistogram::default();
+    for w in &ranks {
+        lifetimes.merge(&w.lifetimes);
+    }
+    for (bound, n) in LIFETIME_BUCKETS.iter().zip(lifetimes.buckets) {
+        let _ = writeln!(
+            out,
+            "{}_bucket{{job=\"{}\",le=\"{}\"}} {}",
+            name, job, bound, n
+        );
+    }
+    let _ = writeln!(
+        out,
+        "{}_bucket{{job=\"{}\",le=\"+Inf\"}} {}",
+        name, job, lifetimes.count
+    );
+    let _ = writeln!(out, "{}_sum{{job=\"{}\"}} {}", name, job, lifetimes.sum);
+    let _ = writeln!(out, "{}_count{{job=\"{}\"}} {}", name, job, lifetimes.count);
+
+    if let Some(leases) = shared.lease_progress() {
+        let name = header(
+            &mut out,
+            "coordinator_lease_items",
+            "gauge",
+            "Work items of the current epoch, by lease state.",
+        );
+        for (state, n) in [("pending", leases.pending), ("leased", leases.leased), ("done", leases.done)] {
+            let _ = writeln!(out, "{}{{job=\"{}\",state=\"{}\"}} {}", name, job, state, n);
+        }
+        let name = header(
+            &mut out,
+            "coordinator_lease_epoch",
+            "gauge",
+            "Current zero-based epoch of the shard lease queue.",
+        );
+        let _ = writeln!(out, "{}{{job=\"{}\"}} {}", name, job, leases.epoch);
+    }
+
+    let name = header(
+        &mut out,
+        "coordinator_checkpoint_gc_reclaimed_bytes_total",
+        "counter",
+        "Bytes of checkpoints deleted by garbage collection.",
+    );
+    let _ = writeln!(
+        out,
+        "{}{{job=\"{}\"}} {}",
+        name,
+        job,
+        shared.gc_reclaimed_bytes.load(Ordering::Relaxed)
+    );
+
+    if let Some(checkpoint) = shared.barrier().latest() {
+        let name = header(
+            &mut out,
+            "coordinator_global_checkpoint_epoch",
+            "gauge",
+            "Epoch of the last complete global checkpoint.",
+        );
+        let _ = writeln!(out, "{}{{job=\"{}\"}} {}", name, job, checkpoint.epoch);
+        let name = header(
+            &mut out,
+            "coordinator_global_checkpoint_timestamp_seconds",
+            "gauge",
+            "When the last complete global checkpoint was committed.",
+        );
+        let _ = writeln!(out, "{}{{job=\"{}\"}} {}", name, job, checkpoint.committed_at);
+    }
+    out
+}
+
+/// Writes the HELP and TYPE lines of a metric family and returns its name.
+fn header<'a>(out: &mut String, name: &'a str, kind: &str, help: &str) -> &'a str {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    name
+}
+
+/// Escapes a label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,241 @@
+use crate::config::Config;
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Decides whether and when a failed rank is restarted.
+///
+/// Delays grow exponentially from `initial` up to `max`, with +/- `jitter`
+/// applied so ranks that died together do not restart in lockstep; the
+/// jittered delay never exceeds `max`. A rank that stayed up for
+/// `reset_after` starts again from `initial`. A rank that used its lifetime
+/// `max_restarts` is given up on, as is one whose process crashed
+/// `crash_loop_max_failures` times within `crash_loop_window`, if set. Only
+/// crash exits count toward the crash loop, not hangs, heartbeat timeouts
+/// or operator kills.
+#[derive(Debug, Clone)]
+pub struct RestartPolicy {
+    initial: Duration,
+    max: Duration,
+    multiplier: f64,
+    jitter: f64,
+    reset_after: Duration,
+    crash_loop_window: Duration,
+    crash_loop_max_failures: usize,
+    max_restarts: usize,
+}
+
+/// Per-rank failure bookkeeping used by [`RestartPolicy`].
+#[derive(Debug, Default)]
+pub struct RestartHistory {
+    /// Crash times inside the crash-loop window.
+    failures: VecDeque<Instant>,
+    /// Failures since the last healthy run; drives the backoff exponent.
+    consecutive: u32,
+    started_at: Option<Instant>,
+}
+
+impl RestartHistory {
+    pub fn on_start(&mut self, now: Instant) {
+        self.started_at = Some(now);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GiveUpReason {
+    MaxRestarts,
+    CrashLoop,
+}
+
+impl GiveUpReason {
+    /// Short, stable name for the event journal.
+    pub fn label(self) -> &'static str {
+        match self {
+            GiveUpReason::MaxRestarts => "max_restarts",
+            GiveUpReason::CrashLoop => "crash_loop",
+        }
+    }
+}
+
+impl fmt::Display for GiveUpReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GiveUpReason::MaxRestarts => f.write_str("max restarts hit"),
+            GiveUpReason::CrashLoop => f.write_str("crash loop detected"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestartDecision {
+    Restart(Duration),
+    GiveUp(GiveUpReason),
+}
+
+impl RestartPolicy {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            initial: Duration::from_millis(config.restart_backoff_initial_ms),
+            max: Duration::from_millis(config.restart_backoff_max_ms),
+            multiplier: config.restart_backoff_multiplier,
+            jitter: config.restart_backoff_jitter,
+            reset_after: Duration::from_secs(config.restart_reset_after),
+            crash_loop_window: Duration::from_secs(config.crash_loop_window),
+            crash_loop_max_failures: config.crash_loop_max_failures,
+            max_restarts: config.max_restarts,
+        }
+    }
+
+    /// Records a failure at `now` and returns what to do about it. `crashed`
+    /// is whether the process exited on its own, as opposed to being killed
+    /// by the coordinator or an operator.
+    pub fn on_failure(
+        &self,
+        history: &mut RestartHistory,
+        restarts: usize,
+        crashed: bool,
+        now: Instant,
+    ) -> RestartDecision {
+        if let Some(started) = history.started_at.take() {
+            if now.duration_since(started) >= self.reset_after {
+                history.consecutive = 0;
+            }
+        }
+
+        if crashed {
+            history.failures.push_back(now);
+        }
+        while let Some(first) = history.failures.front() {
+            if now.duration_since(*first) > self.crash_loop_window {
+                history.failures.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        if self.crash_loop_max_failures > 0 && history.failures.len() >= self.crash_loop_max_failures {
+            return RestartDecision::GiveUp(GiveUpReason::CrashLoop);
+        }
+        if restarts >= self.max_restarts {
+            return RestartDecision::GiveUp(GiveUpReason::MaxRestarts);
+        }
+
+        let delay = self.backoff(history.consecutive);
+        history.consecutive = history.consecutive.saturating_add(1);
+        RestartDecision::Restart(delay)
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        let base = self.initial.as_secs_f64() * self.multiplier.powi(attempt.min(64) as i32);
+        let max = self.max.as_secs_f64();
+        let capped = base.min(max);
+        let spread = capped * self.jitter * (fastrand::f64() * 2.0 - 1.0);
+        Duration::from_secs_f64((capped + spread).clamp(0.0, max))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> RestartPolicy {
+        RestartPolicy {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(8),
+            multiplier: 2.0,
+            jitter: 0.0,
+            reset_after: Duration::from_secs(60),
+            crash_loop_window: Duration::from_secs(30),
+            crash_loop_max_failures: 0,
+            max_restarts: 100,
+        }
+    }
+
+    fn delays(policy: &RestartPolicy, history: &mut RestartHistory, now: Instant, n: usize) -> Vec<u64> {
+        (0..n)
+            .map(|i| match policy.on_failure(history, i, false, now) {
+                RestartDecision::Restart(delay) => delay.as_secs(),
+                decision => panic!("unexpected {:?}", decision),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let mut history = RestartHistory::default();
+        assert_eq!(delays(&policy(), &mut history, Instant::now(), 6), [1, 2, 4, 8, 8, 8]);
+    }
+
+    #[test]
+    fn backoff_starts_over_after_a_healthy_run() {
+        let policy = policy();
+        let mut history = RestartHistory::default();
+        let start = Instant::now();
+        delays(&policy, &mut history, start, 3);
+
+        history.on_start(start);
+        let later = start + Duration::from_secs(10);
+        let decision = policy.on_failure(&mut history, 3, false, later);
+        assert_eq!(decision, RestartDecision::Restart(Duration::from_secs(8)));
+
+        history.on_start(later);
+        let healthy = later + Duration::from_secs(60);
+        let decision = policy.on_failure(&mut history, 4, false, healthy);
+        assert_eq!(decision, RestartDecision::Restart(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn jitter_never_exceeds_max() {
+        let policy = RestartPolicy { jitter: 0.5, ..policy() };
+        for attempt in 0..200 {
+            let delay = policy.backoff(attempt % 8);
+            assert!(delay <= policy.max, "{:?}", delay);
+        }
+    }
+
+    #[test]
+    fn gives_up_after_max_restarts() {
+        let policy = RestartPolicy { max_restarts: 2, ..policy() };
+        let mut history = RestartHistory::default();
+        let now = Instant::now();
+        assert!(matches!(policy.on_failure(&mut history, 1, true, now), RestartDecision::Restart(_)));
+        assert_eq!(
+            policy.on_failure(&mut history, 2, true, now),
+            RestartDecision::GiveUp(GiveUpReason::MaxRestarts)
+        );
+    }
+
+    #[test]
+    fn crash_loop_counts_crashes_within_the_window() {
+        let policy = RestartPolicy { crash_loop_max_failures: 3, ..policy() };
+        let mut history = RestartHistory::default();
+        let start = Instant::now();
+        assert!(matches!(policy.on_failure(&mut history, 0, true, start), RestartDecision::Restart(_)));
+        // The first crash leaves the window before the third one.
+        let later = start + Duration::from_secs(20);
+        assert!(matches!(policy.on_failure(&mut history, 1, true, later), RestartDecision::Restart(_)));
+        let after_window = start + Duration::from_secs(31);
+        assert!(matches!(policy.on_failure(&mut history, 2, true, after_window), RestartDecision::Restart(_)));
+        assert_eq!(
+            policy.on_failure(&mut history, 3, true, after_window),
+            RestartDecision::GiveUp(GiveUpReason::CrashLoop)
+        );
+    }
+
+    #[test]
+    fn crash_loop_ignores_kills_and_is_off_by_default() {
+        let now = Instant::now();
+        let breaker = RestartPolicy { crash_loop_max_failures: 2, ..policy() };
+        let mut history = RestartHistory::default();
+        for restarts in 0..5 {
+            let decision = breaker.on_failure(&mut history, restarts, false, now);
+            assert!(matches!(decision, RestartDecision::Restart(_)));
+        }
+
+        let mut history = RestartHistory::default();
+        for restarts in 0..5 {
+            let decision = policy().on_failure(&mut history, restarts, true, now);
+            assert!(matches!(decision, RestartDecision::Restart(_)));
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use crate::checkpoint;
+use crate::config::Config;
+use crate::coordinator::Shared;
+use crate::events::Event;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::{error, info, warn};
+
+/// Which committed checkpoints of a rank survive a GC pass.
+///
+/// A rank keeps its newest `keep_last` step directories, every step that
+/// is a multiple of `keep_every`, and any step still referenced by its
+/// `LATEST` pointer or by `GLOBAL_LATEST`. Everything else is deleted.
+#[derive(Debug, Clone)]
+pub struct RetentionPolicy {
+    keep_last: usize,
+    keep_every: u64,
+}
+
+impl RetentionPolicy {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            keep_last: config.checkpoint_keep_last,
+            keep_every: config.checkpoint_keep_every,
+        }
+    }
+
+    /// The steps out of `steps` that may be deleted, oldest first.
+    pub fn expired(&self, steps: &BTreeSet<u64>, referenced: &BTreeSet<u64>) -> Vec<u64> {
+        let newest: BTreeSet<u64> = steps.iter().rev().take(self.keep_last).copied().collect();
+        steps
+            .iter()
+            .copied()
+            .filter(|s| !newest.contains(s) && !referenced.contains(s))
+            .filter(|s| self.keep_every == 0 || s % self.keep_every != 0)
+            .collect()
+    }
+}
+
+/// What a pass removed from one rank's directory.
+#[derive(Debug, Default)]
+pub struct Collected {
+    pub steps: Vec<u64>,
+    /// `step_*_tmp_*` directories of writes that never finished.
+    pub orphans: usize,
+    pub bytes: u64,
+}
+
+/// Applies `policy` to one worker directory and removes temporary step
+/// directories that no live process is writing. `writer` is the pid of the
+/// rank's running process, whose in-flight write is left alone.
+pub fn collect_rank(
+    dir: &Path,
+    policy: &RetentionPolicy,
+    referenced: &BTreeSet<u64>,
+    writer: Option<u32>,
+) -> io::Result<Collected> {
+    let mut steps = BTreeSet::new();
+    let mut orphans = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if let Some(step) = checkpoint::parse_step_dir(&name) {
+            steps.insert(step);
+        } else if is_tmp_dir(&name) {
+            // Names without a pid are only cleaned up once nothing runs.
+            let orphaned = match tmp_writer_pid(&name) {
+                Some(pid) => Some(pid) != writer,
+                None => writer.is_none(),
+            };
+            if orphaned {
+                orphans.push(entry.path());
+            }
+        }
+    }
+
+    let mut collected = Collected::default();
+    for step in policy.expired(&steps, referenced) {
+        if let Some(bytes) = remove(&dir.join(format!("step_{}", step))) {
+            collected.steps.push(step);
+            collected.bytes += bytes;
+        }
+    }
+    for path in orphans {
+        if let Some(bytes) = remove(&path) {
+            collected.orphans += 1;
+            collected.bytes += bytes;
+        }
+    }
+    Ok(collected)
+}
+
+/// Runs a GC pass over every rank's checkpoints each `interval`, until the
+/// job stops.
+pub async fn run(shared: Arc<Shared>, interval: Duration) {
+    let policy = RetentionPolicy::from_config(&shared.config);
+    loop {
+        sleep(interval).await;
+        if shared.stopping.load(Ordering::SeqCst) {
+            continue;
+        }
+        let (shared, policy) = (shared.clone(), policy.clone());
+        // Deleting large checkpoints can take a while.
+        let pass = tokio::task::spawn_blocking(move || collect_job(&shared, &policy));
+        if let Err(e) = pass.await {
+            error!("[coord] checkpoint GC failed: {}", e);
+        }
+    }
+}
+
+/// One GC pass over `checkpoint_dir/<job>/worker_*`, including ranks beyond
+/// the current world size.
+fn collect_job(shared: &Shared, policy: &RetentionPolicy) {
+    let job_dir = Path::new(&shared.config.checkpoint_dir).join(&shared.config.job_id);
+    let ranks = match checkpoint::worker_dirs(&job_dir) {
+        Ok(ranks) => ranks,
+        Err(e) => {
+            warn!("[coord] checkpoint GC: failed to read {}: {}", job_dir.display(), e);
+            return;
+        }
+    };
+    let global: BTreeMap<usize, u64> =
+        shared.barrier().latest().map(|c| c.steps.clone()).unwrap_or_default();
+    let writers: HashMap<usize, u32> = shared
+        .workers()
+        .values()
+        .filter_map(|w| Some((w.rank, w.pid()?)))
+        .collect();
+
+    let mut total = 0;
+    for (rank, dir) in ranks {
+        let referenced: BTreeSet<u64> = checkpoint::latest_step(&dir)
+            .into_iter()
+            .chain(global.get(&rank).copied())
+            .collect();
+        let collected = match collect_rank(&dir, policy, &referenced, writers.get(&rank).copied()) {
+            Ok(collected) => collected,
+            Err(e) => {
+                warn!("[coord] checkpoint GC: failed to read {}: {}", dir.display(), e);
+                continue;
+            }
+        };
+        if collected.steps.is_empty() && collected.orphans == 0 {
+            continue;
+        }
+        total += collected.bytes;
+        shared.journal.record(Event::CheckpointsCollected {
+            rank,
+            steps: collected.steps,
+            orphans: collected.orphans,
+            bytes_reclaimed: collected.bytes,
+        });
+    }
+    if total > 0 {
+        shared.gc_reclaimed_bytes.fetch_add(total, Ordering::Relaxed);
+        info!("[coord] checkpoint GC reclaimed {} bytes", total);
+    }
+}
+
+/// A step directory still being written (`step_<N>_tmp_<millis>_<pid>`).
+fn is_tmp_dir(name: &str) -> bool {
+    name.starts_with("step_") && name.contains("_tmp_")
+}
+
+fn tmp_writer_pid(name: &str) -> Option<u32> {
+    name.split_once("_tmp_")?.1.split_once('_')?.1.parse().ok()
+}
+
+/// Deletes a directory tree, returning its size, or `None` if it could not
+/// be removed.
+fn remove(path: &Path) -> Option<u64> {
+    let bytes = tree_size(path);
+    match std::fs::remove_dir_all(path) {
+        Ok(()) => Some(bytes),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => {
+            warn!("[coord] checkpoint GC: failed to remove {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Total size of the files under `path`, not following symlinks.
+fn tree_size(path: &Path) -> u64 {
+    let Ok(meta) = std::fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if !meta.is_dir() {
+        return meta.len();
+    }
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return 0;
+    };
+    entries.flatten().map(|e| tree_size(&e.path())).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn steps(steps: &[u64]) -> BTreeSet<u64> {
+        steps.iter().copied().collect()
+    }
+
+    #[test]
+    fn keeps_the_newest_steps() {
+        let policy = RetentionPolicy { keep_last: 2, keep_every: 0 };
+        assert_eq!(policy.expired(&steps(&[10, 20, 30, 40]), &steps(&[])), [10, 20]);
+        assert!(policy.expired(&steps(&[10]), &steps(&[])).is_empty());
+    }
+
+    #[test]
+    fn keeps_every_nth_step() {
+        let policy = RetentionPolicy { keep_last: 1, keep_every: 100 };
+        assert_eq!(policy.expired(&steps(&[50, 100, 150, 200, 250]), &steps(&[])), [50, 150]);
+    }
+
+    #[test]
+    fn keeps_referenced_steps() {
+        let policy = RetentionPolicy { keep_last: 1, keep_every: 0 };
+        assert_eq!(policy.expired(&steps(&[10, 20, 30]), &steps(&[10])), [20]);
+    }
+}
@@ -0,0 +1,919 @@
+use crate::config::{Config, ResolvedConfig};
+use crate::coordinator::{now_secs, Coordinator, JobStatus, Shared, SlotBudget};
+use crate::events::Event;
+use crate::shutdown::ShutdownSignals;
+use crate::signals::{process_alive, send_signal};
+use crate::state::{self, write_atomic};
+use crate::worker::JobOutcome;
+use anyhow::{anyhow, Context, Result};
+use axum::extract::{Path as UrlPath, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tokio::sync::Notify;
+use tokio::time::sleep;
+use tracing::{error, info, info_span, warn, Instrument};
+
+/// `checkpoint_dir/scheduler.json`.
+pub fn scheduler_path(checkpoint_dir: &Path) -> PathBuf {
+    checkpoint_dir.join("scheduler.json")
+}
+
+/// Where a submitted job is in its lifecycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobPhase {
+    /// Waiting for enough free worker slots.
+    Queued,
+    Running,
+    /// Stopping to make room for a job of higher priority; queued again
+    /// once its workers have checkpointed and exited.
+    Preempting,
+    /// Cancelled while running; its workers are draining.
+    Cancelling,
+    /// Its coordinator returned an outcome.
+    Finished,
+    /// Could not be started, or its coordinator failed.
+    Failed,
+    Cancelled,
+}
+
+impl JobPhase {
+    /// Queued or holding worker slots.
+    fn is_active(self) -> bool {
+        matches!(
+            self,
+            JobPhase::Queued | JobPhase::Running | JobPhase::Preempting | JobPhase::Cancelling
+        )
+    }
+
+    /// A coordinator runs the job's workers.
+    fn has_coordinator(self) -> bool {
+        matches!(self, JobPhase::Running | JobPhase::Preempting | JobPhase::Cancelling)
+    }
+}
+
+impl fmt::Display for JobPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            JobPhase::Queued => "queued",
+            JobPhase::Running => "running",
+            JobPhase::Preempting => "preempting",
+            JobPhase::Cancelling => "cancelling",
+            JobPhase::Finished => "finished",
+            JobPhase::Failed => "failed",
+            JobPhase::Cancelled => "cancelled",
+        };
+        f.write_str(s)
+    }
+}
+
+/// One phase change of a job.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub ts: f64,
+    pub phase: JobPhase,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// A submitted job, as the scheduler tracks and persists it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobRecord {
+    pub job_id: String,
+    /// Higher runs first.
+    pub priority: i64,
+    /// Submission order; breaks ties between equal priorities.
+    pub seq: u64,
+    /// Worker slots the job takes while running: its world size.
+    pub slots: usize,
+    pub phase: JobPhase,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outcome: Option<JobOutcome>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub submitted_at: f64,
+    /// Times the job was stopped for a job of higher priority.
+    #[serde(default)]
+    pub preemptions: u32,
+    /// TCP and UDP ports the job listens on, resolved once at submission.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ports: Vec<u16>,
+    /// Settings submitted with the job, layered over the daemon's.
+    pub config: Map<String, Value>,
+    pub history: Vec<HistoryEntry>,
+}
+
+impl JobRecord {
+    fn transition(&mut self, phase: JobPhase, detail: impl Into<Option<String>>) {
+        self.phase = phase;
+        self.history.push(HistoryEntry {
+            ts: now_secs(),
+            phase,
+            detail: detail.into(),
+        });
+    }
+}
+
+/// Why a submission or cancellation was refused.
+#[derive(Debug)]
+pub enum SchedulerError {
+    UnknownJob(String),
+    Invalid(String),
+    Conflict(String),
+}
+
+impl fmt::Display for SchedulerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchedulerError::UnknownJob(job_id) => write!(f, "unknown job {}", job_id),
+            SchedulerError::Invalid(msg) | SchedulerError::Conflict(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl IntoResponse for SchedulerError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            SchedulerError::UnknownJob(_) => StatusCode::NOT_FOUND,
+            SchedulerError::Invalid(_) => StatusCode::BAD_REQUEST,
+            SchedulerError::Conflict(_) => StatusCode::CONFLICT,
+        };
+        (status, self.to_string()).into_response()
+    }
+}
+
+/// Worker slot usage across the host.
+#[derive(Debug, Clone, Serialize)]
+pub struct SchedulerStatus {
+    pub slots: usize,
+    pub slots_used: usize,
+    pub queued: usize,
+    pub running: usize,
+    pub shutting_down: bool,
+}
+
+/// What survives a daemon restart.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Saved {
+    next_seq: u64,
+    jobs: Vec<JobRecord>,
+}
+
+/// Worker slots a job's running coordinator holds.
+trait HoldsSlots {
+    fn slots(&self) -> usize;
+}
+
+impl HoldsSlots for Arc<Shared> {
+    /// The job's current world size.
+    fn slots(&self) -> usize {
+        self.generation().world_size
+    }
+}
+
+struct Jobs<R = Arc<Shared>> {
+    next_seq: u64,
+    records: BTreeMap<String, JobRecord>,
+    /// Coordinator state of each job with a running coordinator.
+    running: HashMap<String, R>,
+}
+
+/// What the admission loop does next.
+#[derive(Debug, PartialEq)]
+enum Admission {
+    Start(String),
+    /// The next job needs `needed` more slots than are free; the jobs
+    /// behind it wait as well.
+    Blocked { job_id: String, priority: i64, needed: usize },
+}
+
+impl<R: HoldsSlots> Jobs<R> {
+    /// Slots held by the jobs with a running coordinator.
+    fn slots_used(&self) -> usize {
+        self.running.values().map(R::slots).sum()
+    }
+
+    /// The queued job of highest priority, submitted first among equals,
+    /// and whether it fits in what is left of `slots`.
+    fn next_admission(&self, slots: usize) -> Option<Admission> {
+        let free = slots.saturating_sub(self.slots_used());
+        let next = self
+            .records
+            .values()
+            .filter(|r| r.phase == JobPhase::Queued)
+            .min_by_key(|r| (Reverse(r.priority), r.seq))?;
+        if next.slots > free {
+            return Some(Admission::Blocked {
+                job_id: next.job_id.clone(),
+                priority: next.priority,
+                needed: next.slots - free,
+            });
+        }
+        Some(Admission::Start(next.job_id.clone()))
+    }
+
+    /// Running jobs of lower priority than `priority` to stop until `needed`
+    /// more slots are on their way back: lowest priority first, and the
+    /// most recently submitted among equals. None if that would still not
+    /// make room.
+    fn victims(&self, priority: i64, needed: usize) -> Vec<String> {
+        let mut releasing = 0;
+        let mut victims = Vec::new();
+        for (id, running) in &self.running {
+            let Some(record) = self.records.get(id) else {
+                continue;
+            };
+            let slots = running.slots();
+            match record.phase {
+                JobPhase::Preempting | JobPhase::Cancelling => releasing += slots,
+                JobPhase::Running if record.priority < priority => {
+                    victims.push((record.priority, Reverse(record.seq), id.clone(), slots))
+                }
+                _ => {}
+            }
+        }
+        victims.sort();
+        let mut chosen = Vec::new();
+        for (_, _, id, slots) in victims {
+            if releasing >= needed {
+                break;
+            }
+            releasing += slots;
+            chosen.push(id);
+        }
+        if releasing < needed {
+            chosen.clear();
+        }
+        chosen
+    }
+
+    /// Moves a job whose coordinator returned to its next phase. One that
+    /// was stopped for preemption or by the daemon shutting down is queued
+    /// again.
+    fn finish(&mut self, job_id: &str, result: Result<JobOutcome>, shutting_down: bool) {
+        let world_size = self.running.remove(job_id).map(|r| r.slots());
+        let Some(record) = self.records.get_mut(job_id) else {
+            return;
+        };
+        // Queued again, it needs as many slots as it last ran on.
+        record.slots = world_size.unwrap_or(record.slots);
+        match result {
+            Ok(outcome) => {
+                info!("[sched] job {} finished: {}", job_id, outcome);
+                record.outcome = Some(outcome);
+                if record.phase == JobPhase::Cancelling {
+                    record.transition(JobPhase::Cancelled, outcome.to_string());
+                } else if outcome.is_stop() && record.phase == JobPhase::Preempting {
+                    // Resumes from its LATEST checkpoints once admitted again.
+                    record.transition(JobPhase::Queued, format!("preempted ({})", outcome));
+                } else if outcome.is_stop() && shutting_down {
+                    // Resumes from its checkpoints when the daemon is back.
+                    record.transition(JobPhase::Queued, "daemon shut down".to_string());
+                } else {
+                    record.transition(JobPhase::Finished, outcome.to_string());
+                }
+            }
+            Err(e) => {
+                error!("[sched] job {} failed: {:#}", job_id, e);
+                record.error = Some(format!("{:#}", e));
+                record.transition(JobPhase::Failed, None);
+            }
+        }
+    }
+}
+
+/// Daemon mode: runs many jobs, each with its own [`Coordinator`], within
+/// a host-wide budget of worker slots.
+///
+/// Queued jobs are admitted strictly in order of priority, then submission,
+/// once their world size fits in the free slots; a job that does not fit
+/// holds back those behind it. If preemption is enabled, running jobs of
+/// lower priority are stopped to make room for it and queued again.
+///
+/// The queue and every job's history are written to `scheduler.json` on
+/// each change. A restarted daemon stops the workers that jobs which were
+/// running left behind, and puts those jobs back at the head of their
+/// priority, where their coordinators resume them from checkpoints.
+pub struct Scheduler {
+    /// The daemon's job file and CLI flags, under every job's settings.
+    file: Option<PathBuf>,
+    overrides: Value,
+    slots: usize,
+    preemption: bool,
+    path: PathBuf,
+    jobs: Mutex<Jobs>,
+    /// Wakes the admission loop.
+    changed: Notify,
+    shutting_down: AtomicBool,
+}
+
+impl Scheduler {
+    pub async fn open(file: Option<PathBuf>, overrides: Value, config: &Config) -> Result<Arc<Self>> {
+        let slots = match config.scheduler_slots {
+            0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        };
+        let path = scheduler_path(Path::new(&config.checkpoint_dir));
+        let saved: Saved = match std::fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text).with_context(|| format!("invalid {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Saved::default(),
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+        };
+        let active: Vec<&JobRecord> = saved.jobs.iter().filter(|r| r.phase.has_coordinator()).collect();
+        stop_orphans(file.as_deref(), &overrides, &active).await;
+        let mut records = BTreeMap::new();
+        for mut record in saved.jobs {
+            match record.phase {
+                JobPhase::Running | JobPhase::Preempting => {
+                    record.transition(JobPhase::Queued, "daemon restarted".to_string())
+                }
+                JobPhase::Cancelling => record.transition(JobPhase::Cancelled, "daemon restarted".to_string()),
+                _ => {}
+            }
+            // Saved before ports were recorded with the job.
+            if record.phase.is_active() && record.ports.is_empty() {
+                let resolved = ResolvedConfig::load_submission(file.as_deref(), overrides.clone(), record.config.clone());
+                if let Ok(ResolvedConfig { config, .. }) = resolved {
+                    record.ports = listener_ports(&config);
+                }
+            }
+            records.insert(record.job_id.clone(), record);
+        }
+        let queued = records.values().filter(|r| r.phase == JobPhase::Queued).count();
+        info!("[sched] {} worker slots; {} jobs queued", slots, queued);
+        let scheduler = Self {
+            file,
+            overrides,
+            slots,
+            preemption: config.scheduler_preemption,
+            path,
+            jobs: Mutex::new(Jobs {
+                next_seq: saved.next_seq,
+                records,
+                running: HashMap::new(),
+            }),
+            changed: Notify::new(),
+            shutting_down: AtomicBool::new(false),
+        };
+        scheduler.save(&scheduler.jobs());
+        Ok(Arc::new(scheduler))
+    }
+
+    fn jobs(&self) -> MutexGuard<'_, Jobs> {
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn resolve(&self, submitted: &Map<String, Value>) -> Result<ResolvedConfig> {
+        ResolvedConfig::load_submission(self.file.as_deref(), self.overrides.clone(), submitted.clone())
+    }
+
+    /// Queues a job. `config` holds its settings, as in a job file.
+    pub fn submit(&self, priority: i64, config: Map<String, Value>) -> Result<JobRecord, SchedulerError> {
+        if self.shutting_down.load(Ordering::SeqCst) {
+            return Err(SchedulerError::Conflict("scheduler is shutting down".to_string()));
+        }
+        let resolved = self.resolve(&config).map_err(|e| SchedulerError::Invalid(format!("{:#}", e)))?;
+        let job = &resolved.config;
+        if job.world_size > self.slots {
+            return Err(SchedulerError::Invalid(format!(
+                "job needs {} worker slots; the host has {}",
+                job.world_size, self.slots
+            )));
+        }
+
+        let mut jobs = self.jobs();
+        if jobs.records.get(&job.job_id).is_some_and(|r| r.phase.is_active()) {
+            return Err(SchedulerError::Conflict(format!("job {} is already queued or running", job.job_id)));
+        }
+        // Listeners of jobs that run at the same time must not collide.
+        let ports = listener_ports(job);
+        for other in jobs.records.values().filter(|r| r.phase.is_active()) {
+            if let Some(port) = other.ports.iter().find(|p| ports.contains(p)) {
+                return Err(SchedulerError::Conflict(format!("port {} is taken by job {}", port, other.job_id)));
+            }
+        }
+
+        let seq = jobs.next_seq;
+        jobs.next_seq += 1;
+        let mut record = JobRecord {
+            job_id: job.job_id.clone(),
+            priority,
+            seq,
+            slots: job.world_size,
+            phase: JobPhase::Queued,
+            outcome: None,
+            error: None,
+            submitted_at: now_secs(),
+            preemptions: 0,
+            ports,
+            config,
+            history: Vec::new(),
+        };
+        record.transition(JobPhase::Queued, "submitted".to_string());
+        info!("[sched] queued job {} (priority {}, {} slots)", record.job_id, priority, record.slots);
+        jobs.records.insert(record.job_id.clone(), record.clone());
+        self.save(&jobs);
+        drop(jobs);
+        self.changed.notify_one();
+        Ok(record)
+    }
+
+    /// Removes a queued job from the queue, or stops a running one.
+    pub fn cancel(&self, job_id: &str) -> Result<JobRecord, SchedulerError> {
+        let mut jobs = self.jobs();
+        let shared = jobs.running.get(job_id).cloned();
+        let record = jobs
+            .records
+            .get_mut(job_id)
+            .ok_or_else(|| SchedulerError::UnknownJob(job_id.to_string()))?;
+        match record.phase {
+            JobPhase::Queued => record.transition(JobPhase::Cancelled, None),
+            JobPhase::Running | JobPhase::Preempting => {
+                record.transition(JobPhase::Cancelling, None);
+                if let Some(shared) = shared {
+                    shared.request_stop();
+                }
+            }
+            phase => return Err(SchedulerError::Conflict(format!("job {} is {}", job_id, phase))),
+        }
+        info!("[sched] cancelled job {}", job_id);
+        let record = record.clone();
+        self.save(&jobs);
+        drop(jobs);
+        self.changed.notify_one();
+        Ok(record)
+    }
+
+    pub fn status(&self) -> SchedulerStatus {
+        let jobs = self.jobs();
+        let count = |phase| jobs.records.values().filter(|r| r.phase == phase).count();
+        SchedulerStatus {
+            slots: self.slots,
+            slots_used: jobs.slots_used(),
+            queued: count(JobPhase::Queued),
+            running: jobs.running.len(),
+            shutting_down: self.shutting_down.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Starts queued jobs, in order, for as long as the next one fits.
+    async fn admit(self: &Arc<Self>) {
+        while !self.shutting_down.load(Ordering::SeqCst) {
+            let (job_id, submitted) = {
+                let mut jobs = self.jobs();
+                let job_id = match jobs.next_admission(self.slots) {
+                    None => return,
+                    Some(Admission::Blocked { job_id, priority, needed }) => {
+                        if self.preemption {
+                            self.preempt_for(&mut jobs, &job_id, priority, needed);
+                        }
+                        return;
+                    }
+                    Some(Admission::Start(job_id)) => job_id,
+                };
+                let Some(next) = jobs.records.get_mut(&job_id) else {
+                    return;
+                };
+                next.transition(JobPhase::Running, "admitted".to_string());
+                let submitted = next.config.clone();
+                self.save(&jobs);
+                (job_id, submitted)
+            };
+
+            // Preparing a job can hash its dataset; do it without the lock,
+            // off the async workers.
+            let scheduler = self.clone();
+            let started = tokio::task::spawn_blocking(move || scheduler.resolve(&submitted).and_then(Coordinator::new))
+                .await
+                .unwrap_or_else(|e| Err(anyhow!("preparing the job panicked: {}", e)));
+            let mut jobs = self.jobs();
+            let Some(record) = jobs.records.get_mut(&job_id) else {
+                continue;
+            };
+            match started {
+                Ok(_) if record.phase == JobPhase::Cancelling => record.transition(JobPhase::Cancelled, None),
+                Ok(mut coordinator) => {
+                    // A resumed job keeps the world size it was changed to.
+                    record.slots = coordinator.shared().generation().world_size;
+                    info!("[sched] starting job {} on {} slots", job_id, record.slots);
+                    // The daemon stops its jobs itself on SIGTERM.
+                    coordinator.ignore_shutdown_signals();
+                    let (scheduler, id) = (Arc::downgrade(self), job_id.clone());
+                    let budget: SlotBudget = Box::new(move |growth| {
+                        scheduler.upgrade().map_or(Ok(()), |s| s.check_growth(&id, growth))
+                    });
+                    let _ = coordinator.shared().slot_budget.set(budget);
+                    jobs.running.insert(job_id.clone(), coordinator.shared());
+                    self.spawn_job(job_id, coordinator);
+                }
+                Err(e) => {
+                    error!("[sched] failed to start job {}: {:#}", job_id, e);
+                    record.error = Some(format!("{:#}", e));
+                    record.transition(JobPhase::Failed, "failed to start".to_string());
+                }
+            }
+            self.save(&jobs);
+        }
+    }
+
+    /// Whether a running job may take `growth` more worker slots.
+    fn check_growth(&self, job_id: &str, growth: usize) -> Result<(), String> {
+        let jobs = self.jobs();
+        let free = self.slots.saturating_sub(jobs.slots_used());
+        if growth > free {
+            return Err(format!(
+                "job {} needs {} more worker slots; {} of {} are free",
+                job_id, growth, free, self.slots
+            ));
+        }
+        Ok(())
+    }
+
+    /// Stops the running jobs [`Jobs::victims`] picks for a job of
+    /// `priority` that needs `needed` more slots.
+    fn preempt_for(&self, jobs: &mut Jobs, job_id: &str, priority: i64, needed: usize) {
+        let chosen = jobs.victims(priority, needed);
+        if chosen.is_empty() {
+            return;
+        }
+
+        for id in chosen {
+            let Some(record) = jobs.records.get_mut(&id) else {
+                continue;
+            };
+            warn!(
+                "[sched] preempting job {} (priority {}) for job {} (priority {})",
+                id, record.priority, job_id, priority
+            );
+            record.preemptions += 1;
+            record.transition(
+                JobPhase::Preempting,
+                format!("preempted by job {} (priority {})", job_id, priority),
+            );
+            if let Some(shared) = jobs.running.get(&id) {
+                shared.journal.record(Event::JobPreempted {
+                    by_job: job_id.to_string(),
+                    by_priority: priority,
+                });
+                shared.request_stop();
+            }
+        }
+        self.save(jobs);
+    }
+
+    fn spawn_job(self: &Arc<Self>, job_id: String, mut coordinator: Coordinator) {
+        let scheduler = self.clone();
+        let span = info_span!("job", id = %job_id);
+        tokio::spawn(
+            async move {
+                let result = coordinator.run().await;
+                // Releases the job's lock before the job can be admitted again.
+                drop(coordinator);
+                scheduler.job_done(&job_id, result);
+            }
+            .instrument(span),
+        );
+    }
+
+    /// Records how a job's coordinator ended and frees its slots.
+    fn job_done(&self, job_id: &str, result: Result<JobOutcome>) {
+        let shutting_down = self.shutting_down.load(Ordering::SeqCst);
+        let mut jobs = self.jobs();
+        jobs.finish(job_id, result, shutting_down);
+        self.save(&jobs);
+        drop(jobs);
+        self.changed.notify_one();
+    }
+
+    fn save(&self, jobs: &Jobs) {
+        let saved = Saved {
+            next_seq: jobs.next_seq,
+            jobs: jobs.records.values().cloned().collect(),
+        };
+        let result = serde_json::to_vec_pretty(&saved)
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| write_atomic(&self.path, &bytes));
+        if let Err(e) = result {
+            warn!("[sched] failed to persist the job queue: {:#}", e);
+        }
+    }
+
+    /// Serves the submission API and admits jobs until SIGTERM or SIGINT,
+    /// then stops the running jobs and waits for them to drain.
+    pub async fn run(self: Arc<Self>, bind: &str, port: u16) -> Result<()> {
+        let ip: IpAddr = bind.parse().with_context(|| format!("invalid api_bind {:?}", bind))?;
+        let addr = SocketAddr::new(ip, port);
+        let listener = TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("failed to bind scheduler API on {}", addr))?;
+        info!("[sched] scheduler API on http://{}", addr);
+        let app = routes().with_state(self.clone());
+        tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, app).await {
+                error!("[sched] scheduler API failed: {}", e);
+            }
+        });
+
+        let mut signals = ShutdownSignals::new()?;
+        loop {
+            self.admit().await;
+            tokio::select! {
+                _ = self.changed.notified() => {}
+                name = signals.recv() => {
+                    info!("[sched] received {}; waiting for running jobs to stop", name);
+                    break;
+                }
+            }
+        }
+        // Set before any job stops, so that each is queued again.
+        self.shutting_down.store(true, Ordering::SeqCst);
+        for shared in self.jobs().running.values() {
+            shared.request_stop();
+        }
+        while !self.jobs().running.is_empty() {
+            self.changed.notified().await;
+        }
+        info!("[sched] scheduler shutdown");
+        Ok(())
+    }
+}
+
+/// Stops the workers left behind by the coordinators of `jobs`, which ran
+/// under a daemon that died. They hold slots the new daemon does not count;
+/// the jobs resume from their checkpoints once admitted again.
+async fn stop_orphans(file: Option<&Path>, overrides: &Value, jobs: &[&JobRecord]) {
+    let mut orphans = Vec::new();
+    let mut grace = Duration::ZERO;
+    for record in jobs {
+        let Ok(ResolvedConfig { config, .. }) =
+            ResolvedConfig::load_submission(file, overrides.clone(), record.config.clone())
+        else {
+            continue;
+        };
+        let path = state::state_path(Path::new(&config.checkpoint_dir), &config.job_id);
+        let Ok(Some(saved)) = state::load(&path) else {
+            continue;
+        };
+        grace = grace.max(Duration::from_secs(config.shutdown_grace));
+        for r in &saved.workers {
+            let Some(pid) = r.pid.filter(|&pid| state::is_our_worker(pid, &config.job_id, r.rank)) else {
+                continue;
+            };
+            warn!("[sched] stopping orphaned worker of job {} rank={} pid={}", config.job_id, r.rank, pid);
+            if let Err(e) = send_signal(pid, libc::SIGTERM) {
+                warn!("[sched] failed to send SIGTERM to pid={}: {}", pid, e);
+            }
+            orphans.push(pid);
+        }
+    }
+    let deadline = Instant::now() + grace;
+    while orphans.iter().any(|&pid| process_alive(pid)) && Instant::now() < deadline {
+        sleep(Duration::from_millis(100)).await;
+    }
+    for pid in orphans.into_iter().filter(|&pid| process_alive(pid)) {
+        warn!("[sched] orphaned worker pid={} did not stop within grace; killing", pid);
+        let _ = send_signal(pid, libc::SIGKILL);
+    }
+}
+
+/// TCP and UDP ports a job listens on.
+fn listener_ports(config: &Config) -> Vec<u16> {
+    [config.api_port, config.heartbeat_http_port, config.heartbeat_udp_port]
+        .into_iter()
+        .filter(|&p| p != 0)
+        .collect()
+}
+
+#[derive(Debug, Deserialize)]
+struct Submission {
+    #[serde(default)]
+    priority: i64,
+    /// Job settings, as in a job file.
+    #[serde(default)]
+    config: Map<String, Value>,
+}
+
+/// A job and, while it runs, its coordinator's status.
+#[derive(Serialize)]
+struct JobView {
+    #[serde(flatten)]
+    record: JobRecord,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<JobStatus>,
+}
+
+/// The scheduler API:
+///
+/// - `POST /jobs`: queue `{"priority": N, "config": {...}}`
+/// - `GET /jobs`, `GET /jobs/{job_id}`: jobs with their history
+/// - `POST /jobs/{job_id}/cancel`: dequeue or stop a job
+/// - `GET /status`: slot usage
+fn routes() -> Router<Arc<Scheduler>> {
+    Router::new()
+        .route("/jobs", get(list).post(submit))
+        .route("/jobs/{job_id}", get(job))
+        .route("/jobs/{job_id}/cancel", post(cancel))
+        .route("/status", get(status))
+}
+
+async fn submit(
+    State(scheduler): State<Arc<Scheduler>>,
+    Json(body): Json<Submission>,
+) -> Result<(StatusCode, Json<JobRecord>), SchedulerError> {
+    let record = scheduler.submit(body.priority, body.config)?;
+    Ok((StatusCode::CREATED, Json(record)))
+}
+
+async fn list(State(scheduler): State<Arc<Scheduler>>) -> Json<Vec<JobRecord>> {
+    let mut records: Vec<JobRecord> = scheduler.jobs().records.values().cloned().collect();
+    records.sort_by_key(|r| r.seq);
+    Json(records)
+}
+
+async fn job(
+    State(scheduler): State<Arc<Scheduler>>,
+    UrlPath(job_id): UrlPath<String>,
+) -> Result<Json<JobView>, SchedulerError> {
+    let (record, shared) = {
+        let jobs = scheduler.jobs();
+        let record = jobs.records.get(&job_id).cloned();
+        (record, jobs.running.get(&job_id).cloned())
+    };
+    let record = record.ok_or(SchedulerError::UnknownJob(job_id))?;
+    Ok(Json(JobView {
+        record,
+        status: shared.map(|s| s.status()),
+    }))
+}
+
+async fn cancel(
+    State(scheduler): State<Arc<Scheduler>>,
+    UrlPath(job_id): UrlPath<String>,
+) -> Result<Json<JobRecord>, SchedulerError> {
+    Ok(Json(scheduler.cancel(&job_id)?))
+}
+
+async fn status(State(scheduler): State<Arc<Scheduler>>) -> Json<SchedulerStatus> {
+    Json(scheduler.status())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl HoldsSlots for usize {
+        fn slots(&self) -> usize {
+            *self
+        }
+    }
+
+    fn record(job_id: &str, priority: i64, seq: u64, slots: usize, phase: JobPhase) -> JobRecord {
+        JobRecord {
+            job_id: job_id.to_string(),
+            priority,
+            seq,
+            slots,
+            phase,
+            outcome: None,
+            error: None,
+            submitted_at: 0.0,
+            preemptions: 0,
+            ports: Vec::new(),
+            config: Map::new(),
+            history: Vec::new(),
+        }
+    }
+
+    /// Jobs from `records`; those with a coordinator hold their slots.
+    fn queue(records: Vec<JobRecord>) -> Jobs<usize> {
+        let running = records
+            .iter()
+            .filter(|r| r.phase.has_coordinator())
+            .map(|r| (r.job_id.clone(), r.slots))
+            .collect();
+        Jobs {
+            next_seq: records.len() as u64,
+            records: records.into_iter().map(|r| (r.job_id.clone(), r)).collect(),
+            running,
+        }
+    }
+
+    fn start(job_id: &str) -> Option<Admission> {
+        Some(Admission::Start(job_id.to_string()))
+    }
+
+    #[test]
+    fn admits_by_priority_then_submission() {
+        let jobs = queue(vec![
+            record("late", 1, 2, 1, JobPhase::Queued),
+            record("early", 1, 1, 1, JobPhase::Queued),
+            record("low", 0, 0, 1, JobPhase::Queued),
+            record("done", 9, 3, 1, JobPhase::Finished),
+        ]);
+        assert_eq!(jobs.next_admission(4), start("early"));
+
+        let jobs = queue(vec![
+            record("first", 1, 0, 1, JobPhase::Queued),
+            record("urgent", 5, 1, 1, JobPhase::Queued),
+        ]);
+        assert_eq!(jobs.next_admission(4), start("urgent"));
+    }
+
+    #[test]
+    fn a_job_that_does_not_fit_holds_back_the_queue() {
+        let jobs = queue(vec![
+            record("running", 0, 0, 2, JobPhase::Running),
+            record("big", 5, 1, 3, JobPhase::Queued),
+            record("small", 1, 2, 1, JobPhase::Queued),
+        ]);
+        assert_eq!(jobs.slots_used(), 2);
+        let blocked = Admission::Blocked {
+            job_id: "big".to_string(),
+            priority: 5,
+            needed: 1,
+        };
+        assert_eq!(jobs.next_admission(4), Some(blocked));
+        assert_eq!(jobs.next_admission(5), start("big"));
+        assert_eq!(queue(Vec::new()).next_admission(4), None);
+    }
+
+    #[test]
+    fn stopped_jobs_are_queued_again_only_if_preempted_or_shut_down() {
+        let stopped = || Ok(JobOutcome::Stopped);
+        let mut jobs = queue(vec![
+            record("preempted", 0, 0, 2, JobPhase::Preempting),
+            record("cancelled", 0, 1, 1, JobPhase::Cancelling),
+            record("stopped", 0, 2, 1, JobPhase::Running),
+            record("finished", 0, 3, 1, JobPhase::Preempting),
+            record("failed", 0, 4, 1, JobPhase::Running),
+        ]);
+        // A rescaled job is queued again at its last world size.
+        jobs.running.insert("preempted".to_string(), 3);
+
+        jobs.finish("preempted", stopped(), false);
+        jobs.finish("cancelled", stopped(), false);
+        jobs.finish("stopped", stopped(), false);
+        jobs.finish("finished", Ok(JobOutcome::Success), false);
+        jobs.finish("failed", Err(anyhow!("boom")), false);
+        let phase = |id: &str| jobs.records[id].phase;
+        assert_eq!(phase("preempted"), JobPhase::Queued);
+        assert_eq!(jobs.records["preempted"].slots, 3);
+        assert_eq!(phase("cancelled"), JobPhase::Cancelled);
+        assert_eq!(phase("stopped"), JobPhase::Finished);
+        assert_eq!(phase("finished"), JobPhase::Finished);
+        assert_eq!(phase("failed"), JobPhase::Failed);
+        assert_eq!(jobs.records["failed"].error.as_deref(), Some("boom"));
+        assert!(jobs.running.is_empty());
+
+        let mut jobs = queue(vec![record("stopped", 0, 0, 1, JobPhase::Running)]);
+        jobs.finish("stopped", stopped(), true);
+        assert_eq!(jobs.records["stopped"].phase, JobPhase::Queued);
+    }
+
+    #[test]
+    fn preempts_lowest_priority_then_latest_submission() {
+        let jobs = queue(vec![
+            record("old", 1, 0, 1, JobPhase::Running),
+            record("new", 1, 1, 1, JobPhase::Running),
+            record("lowest", 0, 2, 1, JobPhase::Running),
+            record("equal", 5, 3, 1, JobPhase::Running),
+        ]);
+        assert_eq!(jobs.victims(5, 1), ["lowest"]);
+        assert_eq!(jobs.victims(5, 2), ["lowest", "new"]);
+        assert_eq!(jobs.victims(5, 3), ["lowest", "new", "old"]);
+    }
+
+    #[test]
+    fn preempts_nothing_that_would_not_make_room() {
+        let jobs = queue(vec![
+            record("low", 0, 0, 2, JobPhase::Running),
+            record("high", 9, 1, 2, JobPhase::Running),
+        ]);
+        assert!(jobs.victims(5, 3).is_empty());
+        assert!(jobs.victims(0, 1).is_empty());
+    }
+
+    #[test]
+    fn counts_slots_already_being_released() {
+        let jobs = queue(vec![
+            record("draining", 0, 0, 2, JobPhase::Preempting),
+            record("cancelled", 0, 1, 1, JobPhase::Cancelling),
+            record("low", 0, 2, 2, JobPhase::Running),
+        ]);
+        assert!(jobs.victims(5, 3).is_empty());
+        assert_eq!(jobs.victims(5, 4), ["low"]);
+    }
+}
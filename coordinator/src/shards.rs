@@ -0,0 +1,507 @@
+use crate::checkpoint;
+use crate::config::Config;
+use crate::coordinator::now_secs;
+use crate::state::write_atomic;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// Format version of `shards.json`.
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// `checkpoint_dir/<job>/shards.json`.
+pub fn manifest_path(checkpoint_dir: &Path, job_id: &str) -> PathBuf {
+    checkpoint_dir.join(job_id).join("shards.json")
+}
+
+/// Which dataset shards each rank trains on.
+///
+/// Built once per job by the coordinator and handed to workers through
+/// `SHARD_MANIFEST_PATH`, so the assignment no longer depends on each worker
+/// computing it the same way. Rewritten with the next generation whenever
+/// the world size changes. It may be edited by hand; it is validated on
+/// every start.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShardManifest {
+    pub version: u32,
+    pub job_id: String,
+    /// Bumped on every world size change; workers record it in their
+    /// checkpoints.
+    #[serde(default)]
+    pub generation: u64,
+    pub world_size: usize,
+    pub created_at: f64,
+    pub shards: Vec<Shard>,
+    /// Lines of each shard processed in earlier generations. Workers start
+    /// the shard there; a shard whose offset reaches its line count is
+    /// finished and assigned to no rank.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub offsets: BTreeMap<String, u64>,
+    /// Shard names per rank, in training order.
+    pub assignment: BTreeMap<usize, Vec<String>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Shard {
+    /// File name inside `dataset_dir`.
+    pub name: String,
+    pub bytes: u64,
+    pub lines: u64,
+    pub sha256: String,
+}
+
+impl ShardManifest {
+    /// Scans `dataset_dir` and assigns shards round-robin in name order.
+    pub fn build(dataset_dir: &Path, job_id: &str, world_size: usize) -> Result<Self> {
+        let mut shards = Vec::new();
+        for (name, _) in list_shards(dataset_dir)? {
+            shards.push(describe(&dataset_dir.join(&name), name)?);
+        }
+        let mut manifest = Self {
+            version: MANIFEST_VERSION,
+            job_id: job_id.to_string(),
+            generation: 0,
+            world_size,
+            created_at: now_secs(),
+            shards,
+            offsets: BTreeMap::new(),
+            assignment: BTreeMap::new(),
+        };
+        manifest.assign(world_size, &BTreeSet::new());
+        Ok(manifest)
+    }
+
+    fn finished(&self, shard: &Shard) -> bool {
+        self.offsets.get(&shard.name).is_some_and(|o| *o >= shard.lines)
+    }
+
+    /// Deals the unfinished shards round-robin, in name order, to
+    /// `world_size` ranks, leaving out the ranks in `done` unless no other
+    /// rank is left.
+    fn assign(&mut self, world_size: usize, done: &BTreeSet<usize>) {
+        let mut assignment: BTreeMap<usize, Vec<String>> = (0..world_size).map(|r| (r, Vec::new())).collect();
+        let mut ranks: Vec<usize> = (0..world_size).filter(|r| !done.contains(r)).collect();
+        if ranks.is_empty() {
+            ranks = (0..world_size).collect();
+        }
+        let unfinished = self.shards.iter().filter(|s| !self.finished(s));
+        for (i, shard) in unfinished.enumerate() {
+            assignment.entry(ranks[i % ranks.len()]).or_default().push(shard.name.clone());
+        }
+        self.world_size = world_size;
+        self.assignment = assignment;
+    }
+
+    /// The next generation's manifest for `world_size` ranks.
+    ///
+    /// `positions` holds each rank's checkpointed `(shard_idx, line_idx)`
+    /// in this generation: shards before `shard_idx` in its list are done,
+    /// and `line_idx` lines of the one at `shard_idx`. Those become the
+    /// offsets the remaining lines are dealt out from, so nothing
+    /// checkpointed is processed twice and nothing else is skipped. Ranks in
+    /// `done` already finished and get no more work.
+    pub fn remap(
+        &self,
+        positions: &BTreeMap<usize, (usize, u64)>,
+        world_size: usize,
+        done: &BTreeSet<usize>,
+    ) -> Self {
+        let mut offsets = self.progress(positions);
+        offsets.retain(|_, o| *o > 0);
+
+        let mut next = Self {
+            generation: self.generation + 1,
+            created_at: now_secs(),
+            offsets,
+            ..self.clone()
+        };
+        next.assign(world_size, done);
+        next
+    }
+
+    /// Whether `rank` has processed every line assigned to it, given its
+    /// checkpointed `(shard_idx, line_idx)` in this generation, if any.
+    pub fn rank_done(&self, rank: usize, position: Option<(usize, u64)>) -> bool {
+        let positions = position.map(|p| (rank, p)).into_iter().collect();
+        let offsets = self.progress(&positions);
+        let Some(names) = self.assignment.get(&rank) else {
+            return true;
+        };
+        names.iter().all(|name| {
+            let lines = self.shards.iter().find(|s| s.name == *name).map_or(0, |s| s.lines);
+            offsets.get(name).copied().unwrap_or_default() >= lines
+        })
+    }
+
+    /// Lines of each shard processed so far, given each rank's
+    /// `(shard_idx, line_idx)` in this generation (see [`Self::remap`]).
+    pub fn progress(&self, positions: &BTreeMap<usize, (usize, u64)>) -> BTreeMap<String, u64> {
+        let lines: BTreeMap<&str, u64> = self.shards.iter().map(|s| (s.name.as_str(), s.lines)).collect();
+        let mut offsets = self.offsets.clone();
+        for (rank, &(shard_idx, line_idx)) in positions {
+            let Some(names) = self.assignment.get(rank) else {
+                continue;
+            };
+            for (i, name) in names.iter().enumerate().take(shard_idx + 1) {
+                let total = lines.get(name.as_str()).copied().unwrap_or_default();
+                let done = if i < shard_idx { total } else { line_idx.min(total) };
+                let offset = offsets.entry(name.clone()).or_default();
+                *offset = (*offset).max(done);
+            }
+        }
+        offsets
+    }
+
+    /// Lines in the whole dataset.
+    pub fn total_lines(&self) -> u64 {
+        self.shards.iter().map(|s| s.lines).sum()
+    }
+
+    /// Checks that every shard goes to exactly one existing rank.
+    pub fn validate(&self, world_size: usize) -> Result<()> {
+        if self.version != MANIFEST_VERSION {
+            bail!("unsupported shard manifest version {} (expected {})", self.version, MANIFEST_VERSION);
+        }
+        if self.world_size != world_size {
+            bail!(
+                "shard manifest was built for world_size {}, job runs with {}",
+                self.world_size,
+                world_size
+            );
+        }
+        let known: BTreeSet<&str> = self.shards.iter().map(|s| s.name.as_str()).collect();
+        if known.len() != self.shards.len() {
+            bail!("shard manifest lists a shard twice");
+        }
+        let finished: BTreeSet<&str> = self.shards.iter().filter(|s| self.finished(s)).map(|s| s.name.as_str()).collect();
+        let mut seen = finished.clone();
+        for (rank, names) in &self.assignment {
+            if *rank >= world_size {
+                bail!("shard manifest assigns shards to rank {} outside world_size {}", rank, world_size);
+            }
+            for name in names {
+                if !known.contains(name.as_str()) {
+                    bail!("shard manifest assigns unknown shard {} to rank {}", name, rank);
+                }
+                if finished.contains(name.as_str()) {
+                    bail!("shard manifest assigns finished shard {} to rank {}", name, rank);
+                }
+                if !seen.insert(name.as_str()) {
+                    bail!("shard {} is assigned to more than one rank", name);
+                }
+            }
+        }
+        if let Some(missing) = known.difference(&seen).next() {
+            bail!("shard {} is not assigned to any rank", missing);
+        }
+        Ok(())
+    }
+
+    /// Checks the manifest still describes the files in `dataset_dir`.
+    /// Compares names and sizes only; workers verify checksums on load.
+    pub fn check_dataset(&self, dataset_dir: &Path) -> Result<()> {
+        let on_disk = list_shards(dataset_dir)?;
+        let listed: Vec<(String, u64)> = self.shards.iter().map(|s| (s.name.clone(), s.bytes)).collect();
+        if on_disk != listed {
+            bail!(
+                "{} no longer matches the job's shard manifest; \
+                 start a new job ID or delete the manifest to rebuild it",
+                dataset_dir.display()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The job's manifest as found at startup.
+pub struct Prepared {
+    pub manifest: ShardManifest,
+    /// Set if the job was started at a new world size. The manifest must be
+    /// [`rescale`]d to it once the previous run's workers are gone.
+    pub resize_to: Option<usize>,
+}
+
+/// Loads and validates the job's manifest, or builds and writes it on first
+/// start. `None` for S3 datasets and missing dataset directories.
+///
+/// A manifest for a different world size needs a resize, unless it comes
+/// from an elastic resize and the job is resumed: a relaunched coordinator
+/// keeps the size the job was last scaled to.
+pub fn prepare(config: &Config) -> Result<Option<Prepared>> {
+    if config.use_s3 {
+        info!("[coord] S3 dataset; workers assign shards themselves");
+        return Ok(None);
+    }
+    let dataset_dir = Path::new(&config.dataset_dir);
+    if !dataset_dir.is_dir() {
+        warn!("[coord] dataset_dir {} does not exist; no shard manifest", dataset_dir.display());
+        return Ok(None);
+    }
+    let path = manifest_path(Path::new(&config.checkpoint_dir), &config.job_id);
+
+    let mut resize_to = None;
+    let manifest = if path.exists() {
+        let content = std::fs::read_to_string(&path).with_context(|| format!("failed to read {}", path.display()))?;
+        let manifest: ShardManifest =
+            serde_json::from_str(&content).with_context(|| format!("invalid shard manifest {}", path.display()))?;
+        manifest
+            .validate(manifest.world_size)
+            .with_context(|| format!("invalid shard manifest {}", path.display()))?;
+        manifest.check_dataset(dataset_dir)?;
+        if manifest.world_size == config.world_size {
+            manifest
+        } else if config.resume && manifest.generation > 0 {
+            info!(
+                "[coord] keeping world_size {} of generation {} (configured {})",
+                manifest.world_size, manifest.generation, config.world_size
+            );
+            manifest
+        } else {
+            resize_to = Some(config.world_size);
+            manifest
+        }
+    } else {
+        let manifest = ShardManifest::build(dataset_dir, &config.job_id, config.world_size)?;
+        manifest.validate(config.world_size)?;
+        write_atomic(&path, &serde_json::to_vec_pretty(&manifest)?)?;
+        manifest
+    };
+
+    if manifest.shards.is_empty() {
+        warn!("[coord] no shard_* files in {}", dataset_dir.display());
+    }
+    info!(
+        "[coord] shard manifest {}: {} shards over {} ranks (generation {})",
+        path.display(),
+        manifest.shards.len(),
+        manifest.world_size,
+        manifest.generation
+    );
+    Ok(Some(Prepared { manifest, resize_to }))
+}
+
+/// Writes the next generation's manifest for `world_size` ranks, resuming
+/// from each rank's latest checkpoint and dealing nothing to the ranks in
+/// `done`. The workers must be stopped.
+pub fn rescale(
+    config: &Config,
+    manifest: &ShardManifest,
+    world_size: usize,
+    done: &BTreeSet<usize>,
+) -> Result<ShardManifest> {
+    // Leased work is tracked by the lease queue, not by rank positions.
+    let positions = if config.shard_leases {
+        BTreeMap::new()
+    } else {
+        checkpoint_positions(config, manifest)
+    };
+    let next = manifest.remap(&positions, world_size, done);
+    next.validate(world_size)?;
+    let path = manifest_path(Path::new(&config.checkpoint_dir), &config.job_id);
+    write_atomic(&path, &serde_json::to_vec_pretty(&next)?)?;
+    let finished = next.shards.iter().filter(|s| next.finished(s)).count();
+    info!(
+        "[coord] remapped shards for world_size {} (generation {}): {} of {} shards finished",
+        world_size,
+        next.generation,
+        finished,
+        next.shards.len()
+    );
+    Ok(next)
+}
+
+/// `(shard_idx, line_idx)` of every rank whose latest checkpoint was taken
+/// in the manifest's generation. Older checkpoints carry no progress that
+/// is not already in the offsets.
+pub fn checkpoint_positions(config: &Config, manifest: &ShardManifest) -> BTreeMap<usize, (usize, u64)> {
+    (0..manifest.world_size)
+        .filter_map(|rank| Some((rank, checkpoint_position(config, manifest, rank)?)))
+        .collect()
+}
+
+/// `(shard_idx, line_idx)` of `rank`'s latest checkpoint, if it was taken
+/// in the manifest's generation.
+pub fn checkpoint_position(config: &Config, manifest: &ShardManifest, rank: usize) -> Option<(usize, u64)> {
+    let dir = checkpoint::worker_dir(Path::new(&config.checkpoint_dir), &config.job_id, rank);
+    let state = checkpoint::latest_state(&dir)?;
+    let field = |key: &str| state.get(key).and_then(|v| v.as_u64()).unwrap_or_default();
+    (field("generation") == manifest.generation).then(|| (field("shard_idx") as usize, field("line_idx")))
+}
+
+/// `shard_*` files in `dataset_dir` with their sizes, sorted by name.
+fn list_shards(dataset_dir: &Path) -> Result<Vec<(String, u64)>> {
+    let mut shards = Vec::new();
+    let entries = std::fs::read_dir(dataset_dir).with_context(|| format!("failed to read {}", dataset_dir.display()))?;
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with("shard_") && entry.file_type()?.is_file() {
+            shards.push((name, entry.metadata()?.len()));
+        }
+    }
+    shards.sort();
+    Ok(shards)
+}
+
+fn describe(path: &Path, name: String) -> Result<Shard> {
+    let mut file = std::fs::File::open(path).with_context(|| format!("failed to read shard {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let (mut bytes, mut lines, mut last) = (0u64, 0u64, b'\n');
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        bytes += n as u64;
+        lines += buf[..n].iter().filter(|b| **b == b'\n').count() as u64;
+        last = buf[n - 1];
+    }
+    // A final line without a trailing newline still counts.
+    if last != b'\n' {
+        lines += 1;
+    }
+    Ok(Shard {
+        name,
+        bytes,
+        lines,
+        sha256: format!("{:x}", hasher.finalize()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(i: usize) -> String {
+        format!("shard_{:05}.txt", i)
+    }
+
+    /// A manifest over shards of `lines` lines each, dealt to `world_size`.
+    fn manifest(lines: &[u64], world_size: usize) -> ShardManifest {
+        let shards = lines
+            .iter()
+            .enumerate()
+            .map(|(i, &lines)| Shard { name: name(i), bytes: lines * 10, lines, sha256: String::new() })
+            .collect();
+        let mut manifest = ShardManifest {
+            version: MANIFEST_VERSION,
+            job_id: "job".to_string(),
+            generation: 0,
+            world_size,
+            created_at: 0.0,
+            shards,
+            offsets: BTreeMap::new(),
+            assignment: BTreeMap::new(),
+        };
+        manifest.assign(world_size, &BTreeSet::new());
+        manifest
+    }
+
+    #[test]
+    fn shards_are_dealt_round_robin() {
+        let m = manifest(&[10; 5], 2);
+        assert_eq!(m.assignment[&0], [name(0), name(2), name(4)]);
+        assert_eq!(m.assignment[&1], [name(1), name(3)]);
+        m.validate(2).unwrap();
+    }
+
+    #[test]
+    fn validate_rejects_another_world_size() {
+        let m = manifest(&[10; 4], 2);
+        assert!(m.validate(3).is_err());
+    }
+
+    #[test]
+    fn validate_rejects_a_shard_assigned_twice_or_not_at_all() {
+        let mut m = manifest(&[10; 4], 2);
+        m.assignment.get_mut(&1).unwrap().push(name(0));
+        assert!(m.validate(2).is_err());
+
+        let mut m = manifest(&[10; 4], 2);
+        m.assignment.get_mut(&1).unwrap().clear();
+        assert!(m.validate(2).is_err());
+    }
+
+    #[test]
+    fn validate_rejects_unknown_shards_and_ranks() {
+        let mut m = manifest(&[10; 4], 2);
+        m.assignment.get_mut(&0).unwrap().push("shard_99999.txt".to_string());
+        assert!(m.validate(2).is_err());
+
+        let mut m = manifest(&[10; 4], 2);
+        let moved = m.assignment.remove(&1).unwrap();
+        m.assignment.insert(2, moved);
+        assert!(m.validate(2).is_err());
+    }
+
+    #[test]
+    fn validate_rejects_another_version() {
+        let mut m = manifest(&[10; 2], 2);
+        m.version = MANIFEST_VERSION + 1;
+        assert!(m.validate(2).is_err());
+    }
+
+    #[test]
+    fn remap_resumes_from_checkpointed_positions() {
+        let m = manifest(&[10; 4], 2);
+        // Rank 0 finished shard 0 and read 4 lines of shard 2; rank 1 read
+        // 7 lines of shard 1.
+        let positions = BTreeMap::from([(0, (1, 4)), (1, (0, 7))]);
+        let next = m.remap(&positions, 3, &BTreeSet::new());
+        assert_eq!(next.generation, 1);
+        assert_eq!(next.world_size, 3);
+        assert_eq!(next.offsets, BTreeMap::from([(name(0), 10), (name(1), 7), (name(2), 4)]));
+        assert_eq!(next.assignment[&0], [name(1)]);
+        assert_eq!(next.assignment[&1], [name(2)]);
+        assert_eq!(next.assignment[&2], [name(3)]);
+        next.validate(3).unwrap();
+    }
+
+    #[test]
+    fn remap_never_moves_offsets_back() {
+        let mut m = manifest(&[10; 2], 2);
+        m.offsets.insert(name(0), 6);
+        let next = m.remap(&BTreeMap::from([(0, (0, 2))]), 2, &BTreeSet::new());
+        assert_eq!(next.offsets[&name(0)], 6);
+    }
+
+    #[test]
+    fn remap_gives_no_work_to_finished_ranks() {
+        let m = manifest(&[10; 4], 3);
+        let next = m.remap(&BTreeMap::new(), 3, &BTreeSet::from([0]));
+        assert!(next.assignment[&0].is_empty());
+        assert_eq!(next.assignment[&1], [name(0), name(2)]);
+        assert_eq!(next.assignment[&2], [name(1), name(3)]);
+        next.validate(3).unwrap();
+
+        // With no other rank left, the finished ones take the work.
+        let next = m.remap(&BTreeMap::new(), 2, &BTreeSet::from([0, 1]));
+        assert_eq!(next.assignment[&0], [name(0), name(2)]);
+    }
+
+    #[test]
+    fn rank_is_done_once_its_last_shard_is() {
+        let m = manifest(&[10; 4], 2);
+        assert!(!m.rank_done(0, None));
+        assert!(!m.rank_done(0, Some((1, 9))));
+        assert!(m.rank_done(0, Some((1, 10))));
+        // A rank with nothing assigned has nothing left to do.
+        let next = m.remap(&BTreeMap::new(), 3, &BTreeSet::from([2]));
+        assert!(next.rank_done(2, None));
+    }
+
+    #[test]
+    fn validate_rejects_an_assigned_finished_shard() {
+        let mut m = manifest(&[10; 2], 2);
+        m.offsets.insert(name(0), 10);
+        assert!(m.validate(2).is_err());
+        m.assign(2, &BTreeSet::new());
+        m.validate(2).unwrap();
+    }
+}
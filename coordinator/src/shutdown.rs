@@ -0,0 +1,157 @@
+use crate::coordinator::Shared;
+use crate::events::Event;
+use crate::signals::send_signal;
+use crate::worker::{ExitInfo, FailureReason, JobOutcome, WorkerState};
+use anyhow::Result;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio::time::{sleep, Instant};
+use tracing::{info, warn};
+
+/// SIGTERM/SIGINT listener. Created once so signals delivered between
+/// monitor ticks are not lost.
+pub struct ShutdownSignals {
+    /// SIGTERM and SIGINT; `None` if they are left to someone else.
+    streams: Option<(Signal, Signal)>,
+}
+
+impl ShutdownSignals {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            streams: Some((signal(SignalKind::terminate())?, signal(SignalKind::interrupt())?)),
+        })
+    }
+
+    /// Never resolves; for a job that its caller stops.
+    pub fn ignored() -> Self {
+        Self { streams: None }
+    }
+
+    /// Resolves with the signal name once either signal arrives.
+    pub async fn recv(&mut self) -> &'static str {
+        let Some((term, int)) = &mut self.streams else {
+            return std::future::pending().await;
+        };
+        tokio::select! {
+            _ = term.recv() => "SIGTERM",
+            _ = int.recv() => "SIGINT",
+        }
+    }
+}
+
+/// Stops the job: cancels pending restarts, sends SIGTERM to every worker,
+/// waits up to `grace` for them to checkpoint and exit, then SIGKILLs the
+/// rest.
+///
+/// Returns `Stopped` if every worker exited cleanly, `StopTimedOut` if any
+/// had to be killed, and `StopFailed` if any exited with an error.
+pub async fn drain(shared: &Shared, grace: Duration) -> JobOutcome {
+    shared.stopping.store(true, Ordering::SeqCst);
+
+    {
+        let mut workers = shared.workers();
+        for w in workers.values_mut() {
+            if let Some(task) = w.restart_task.take() {
+                task.abort();
+            }
+            w.restart_at = None;
+            match w.pid() {
+                Some(pid) => {
+                    if let Err(e) = send_signal(pid, libc::SIGTERM) {
+                        warn!("[coord] failed to send SIGTERM to rank={} pid={}: {}", w.rank, pid, e);
+                    }
+                }
+                None if !w.state.is_terminal() => {
+                    w.state = WorkerState::Stopped;
+                    shared.journal.record(Event::WorkerStopped {
+                        rank: w.rank,
+                        pid: None,
+                        code: None,
+                        signal: None,
+                        killed: false,
+                    });
+                }
+                None => {}
+            }
+        }
+    }
+    info!("[coord] draining workers (grace {:?})", grace);
+
+    let deadline = Instant::now() + grace;
+    let mut failed = false;
+    loop {
+        if let Some(outcome) = reap(shared, deadline, &mut failed) {
+            return outcome;
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+}
+
+/// One pass of [`drain`]: collects exited workers and, past the deadline,
+/// kills the rest. Returns the outcome once no worker process is left.
+fn reap(shared: &Shared, deadline: Instant, failed: &mut bool) -> Option<JobOutcome> {
+    let mut workers = shared.workers();
+    for w in workers.values_mut() {
+        let pid = w.pid();
+        let Some(process) = w.process.as_mut() else {
+            continue;
+        };
+        match process.try_wait() {
+            Ok(Some(status)) => {
+                shared.journal.record(Event::WorkerStopped {
+                    rank: w.rank,
+                    pid,
+                    code: status.code,
+                    signal: status.signal,
+                    killed: false,
+                });
+                w.reaped();
+                // An adopted worker's status is unknown; give it the benefit of the doubt.
+                if status.success() || status.stopped() || status == ExitInfo::UNKNOWN {
+                    info!("[coord] worker rank={} stopped cleanly", w.rank);
+                } else {
+                    warn!("[coord] worker rank={} exited ({}) while draining", w.rank, status);
+                    w.last_failure = Some(FailureReason::exited(status));
+                    *failed = true;
+                }
+                w.state = WorkerState::Stopped;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                warn!("[coord] failed to check worker {}: {}", w.rank, e);
+                shared.journal.record(Event::WorkerStopped {
+                    rank: w.rank,
+                    pid,
+                    code: None,
+                    signal: None,
+                    killed: true,
+                });
+                w.kill();
+                w.last_failure = Some(FailureReason::PollError);
+                w.state = WorkerState::Stopped;
+                *failed = true;
+            }
+        }
+    }
+
+    if workers.values().all(|w| w.process.is_none()) {
+        return Some(if *failed { JobOutcome::StopFailed } else { JobOutcome::Stopped });
+    }
+    if Instant::now() >= deadline {
+        for w in workers.values_mut().filter(|w| w.process.is_some()) {
+            warn!("[coord] worker rank={} did not stop within grace; killing", w.rank);
+            shared.journal.record(Event::WorkerStopped {
+                rank: w.rank,
+                pid: w.pid(),
+                code: None,
+                signal: None,
+                killed: true,
+            });
+            w.kill();
+            w.state = WorkerState::Stopped;
+        }
+        return Some(JobOutcome::StopTimedOut);
+    }
+    None
+}
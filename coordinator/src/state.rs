@@ -0,0 +1,177 @@
+use crate::coordinator::{now_secs, Generation};
+use crate::signals::process_alive;
+use crate::worker::{Worker, WorkerProcess, WorkerState};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// `checkpoint_dir/<job>/coordinator_state.json`.
+pub fn state_path(checkpoint_dir: &Path, job_id: &str) -> PathBuf {
+    checkpoint_dir.join(job_id).join("coordinator_state.json")
+}
+
+/// `checkpoint_dir/<job>/coordinator.lock`, held by the job's coordinator
+/// and by `coordinator ckpt rollback` for as long as they run.
+pub fn lock_path(checkpoint_dir: &Path, job_id: &str) -> PathBuf {
+    checkpoint_dir.join(job_id).join("coordinator.lock")
+}
+
+/// Takes the job's lock without waiting; `None` if another process holds
+/// it. The lock goes with the returned file, or with its holder's death.
+pub fn try_lock(checkpoint_dir: &Path, job_id: &str) -> Result<Option<File>> {
+    let path = lock_path(checkpoint_dir, job_id);
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    }
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    match file.try_lock() {
+        Ok(()) => Ok(Some(file)),
+        Err(TryLockError::WouldBlock) => Ok(None),
+        Err(TryLockError::Error(e)) => Err(e).with_context(|| format!("failed to lock {}", path.display())),
+    }
+}
+
+/// What the coordinator persists so a relaunched coordinator can pick the
+/// job up instead of spawning duplicates next to the orphaned workers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JobState {
+    pub job_id: String,
+    /// World size change generation the ranks below were launched in.
+    #[serde(default)]
+    pub generation: u64,
+    pub world_size: usize,
+    pub coordinator_pid: u32,
+    pub updated_at: f64,
+    pub workers: Vec<RankState>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RankState {
+    pub rank: usize,
+    pub state: WorkerState,
+    pub pid: Option<u32>,
+    pub restarts: usize,
+    /// When the current process was spawned.
+    pub started_at: f64,
+    #[serde(default)]
+    pub restart_reasons: BTreeMap<String, u64>,
+    /// The process writes its output into pipes read by the coordinator
+    /// that spawned it, as it does whenever `log_dir` is set. Once that
+    /// coordinator is gone its next write fails, so it is replaced on
+    /// resume rather than adopted.
+    #[serde(default)]
+    pub piped_output: bool,
+}
+
+impl JobState {
+    /// `piped_output` is whether this coordinator captures the output of
+    /// the processes it spawns.
+    pub fn capture(
+        job_id: &str,
+        generation: Generation,
+        workers: &HashMap<usize, Worker>,
+        piped_output: bool,
+    ) -> Self {
+        let mut ranks: Vec<RankState> = workers
+            .values()
+            .map(|w| RankState {
+                rank: w.rank,
+                state: w.state,
+                pid: w.pid(),
+                restarts: w.restarts,
+                started_at: w.started_at,
+                restart_reasons: w.restart_reasons.clone(),
+                piped_output: piped_output && matches!(w.process, Some(WorkerProcess::Spawned(_))),
+            })
+            .collect();
+        ranks.sort_by_key(|r| r.rank);
+        Self {
+            job_id: job_id.to_string(),
+            generation: generation.number,
+            world_size: generation.world_size,
+            coordinator_pid: std::process::id(),
+            updated_at: now_secs(),
+            workers: ranks,
+        }
+    }
+
+    /// Whether every rank reached an end that a resume should not undo.
+    /// Ranks stopped by a shutdown are resumed.
+    pub fn finished(&self) -> bool {
+        self.workers
+            .iter()
+            .all(|r| matches!(r.state, WorkerState::Succeeded | WorkerState::Exhausted))
+    }
+
+    /// Same content, ignoring when it was written.
+    pub fn same_as(&self, other: &JobState) -> bool {
+        JobState { updated_at: 0.0, ..self.clone() } == JobState { updated_at: 0.0, ..other.clone() }
+    }
+}
+
+pub fn load(path: &Path) -> Result<Option<JobState>> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+    };
+    let state = serde_json::from_str(&content).with_context(|| {
+        format!("invalid coordinator state in {} (delete it to start fresh)", path.display())
+    })?;
+    Ok(Some(state))
+}
+
+pub fn save(path: &Path, state: &JobState) -> Result<()> {
+    write_atomic(path, &serde_json::to_vec_pretty(state)?)
+}
+
+/// Writes to a temporary file and renames it into place, so a crash never
+/// leaves a torn file behind.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let mut file = std::fs::File::create(&tmp).with_context(|| format!("failed to write {}", tmp.display()))?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp, path).with_context(|| format!("failed to replace {}", path.display()))?;
+    Ok(())
+}
+
+/// Whether `pid` is still the worker a previous coordinator spawned for
+/// `rank` of `job_id`, judged by the `JOB_ID` and `RANK` variables in its
+/// environment. Guards against killing or adopting a process that merely
+/// reuses the pid. Where `/proc` is unavailable, liveness has to do.
+pub fn is_our_worker(pid: u32, job_id: &str, rank: usize) -> bool {
+    if !process_alive(pid) {
+        return false;
+    }
+    let environ = match std::fs::read(format!("/proc/{}/environ", pid)) {
+        Ok(environ) => environ,
+        // No procfs to check against; trust the pid.
+        Err(_) if !Path::new("/proc/self").exists() => return true,
+        Err(_) => return false,
+    };
+    let mut job_matches = false;
+    let mut rank_matches = false;
+    for var in environ.split(|b| *b == 0) {
+        let var = String::from_utf8_lossy(var);
+        if let Some(v) = var.strip_prefix("JOB_ID=") {
+            job_matches = v == job_id;
+        } else if let Some(v) = var.strip_prefix("RANK=") {
+            rank_matches = v == rank.to_string();
+        }
+    }
+    job_matches && rank_matches
+}
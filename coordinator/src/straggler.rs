@@ -0,0 +1,157 @@
+use crate::config::Config;
+use std::collections::{BTreeMap, HashMap};
+
+/// Seconds between two looks at the step rates.
+const CHECK_INTERVAL_SECS: f64 = 5.0;
+
+/// What the coordinator does about a straggler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StragglerPolicy {
+    /// Only warn and journal it.
+    Log,
+    /// Restart the rank, in case the slowness is local to its process.
+    Restart,
+    /// Requeue the rank's leased work item so a faster rank takes it.
+    Reshard,
+}
+
+impl StragglerPolicy {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "log" => Some(StragglerPolicy::Log),
+            "restart" => Some(StragglerPolicy::Restart),
+            "reshard" => Some(StragglerPolicy::Reshard),
+            _ => None,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            StragglerPolicy::Log => "log",
+            StragglerPolicy::Restart => "restart",
+            StragglerPolicy::Reshard => "reshard",
+        }
+    }
+}
+
+/// A rank that fell behind the others.
+#[derive(Debug, Clone, Copy)]
+pub struct Straggler {
+    pub rank: usize,
+    pub steps_per_sec: f64,
+    pub median: f64,
+}
+
+/// Compares the step rates of the running ranks. A rank whose rate stays
+/// below `threshold` times the median for `grace` is reported, and again
+/// every `grace` for as long as it stays there.
+#[derive(Debug)]
+pub struct StragglerDetector {
+    pub policy: StragglerPolicy,
+    threshold: f64,
+    grace: f64,
+    next_check: f64,
+    /// When each rank currently below the threshold fell (or was last
+    /// reported) below it.
+    slow_since: HashMap<usize, f64>,
+}
+
+impl StragglerDetector {
+    /// `None` when straggler detection is disabled.
+    pub fn from_config(config: &Config) -> Option<Self> {
+        if config.straggler_threshold <= 0.0 {
+            return None;
+        }
+        Some(Self {
+            policy: StragglerPolicy::parse(&config.straggler_policy)?,
+            threshold: config.straggler_threshold,
+            grace: config.straggler_grace as f64,
+            next_check: 0.0,
+            slow_since: HashMap::new(),
+        })
+    }
+
+    /// Whether it is time for another [`Self::check`].
+    pub fn due(&mut self, now: f64) -> bool {
+        if now < self.next_check {
+            return false;
+        }
+        self.next_check = now + CHECK_INTERVAL_SECS;
+        true
+    }
+
+    /// The stragglers among the ranks in `rates` (steps per second). Needs
+    /// at least two ranks to compare.
+    pub fn check(&mut self, now: f64, rates: &BTreeMap<usize, f64>) -> Vec<Straggler> {
+        let median = median(rates.values().copied());
+        let Some(median) = median.filter(|&m| rates.len() >= 2 && m > 0.0) else {
+            self.slow_since.clear();
+            return Vec::new();
+        };
+        self.slow_since.retain(|rank, _| rates.contains_key(rank));
+
+        let mut stragglers = Vec::new();
+        for (&rank, &steps_per_sec) in rates {
+            if steps_per_sec >= self.threshold * median {
+                self.slow_since.remove(&rank);
+                continue;
+            }
+            let since = self.slow_since.entry(rank).or_insert(now);
+            if now - *since >= self.grace {
+                *since = now;
+                stragglers.push(Straggler {
+                    rank,
+                    steps_per_sec,
+                    median,
+                });
+            }
+        }
+        stragglers
+    }
+}
+
+fn median(values: impl Iterator<Item = f64>) -> Option<f64> {
+    let mut values: Vec<f64> = values.collect();
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    Some(if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn median_of_odd_and_even_counts() {
+        assert_eq!(median([3.0, 1.0, 2.0].into_iter()), Some(2.0));
+        assert_eq!(median([4.0, 1.0, 3.0, 2.0].into_iter()), Some(2.5));
+        assert_eq!(median([5.0].into_iter()), Some(5.0));
+        assert_eq!(median(std::iter::empty()), None);
+    }
+
+    #[test]
+    fn straggler_is_reported_after_the_grace_period() {
+        let mut detector = StragglerDetector {
+            policy: StragglerPolicy::Log,
+            threshold: 0.5,
+            grace: 10.0,
+            next_check: 0.0,
+            slow_since: HashMap::new(),
+        };
+        let rates = BTreeMap::from([(0, 10.0), (1, 10.0), (2, 4.0)]);
+        assert!(detector.check(0.0, &rates).is_empty());
+        let found = detector.check(10.0, &rates);
+        assert_eq!(found.iter().map(|s| s.rank).collect::<Vec<_>>(), [2]);
+        assert_eq!(found[0].median, 10.0);
+        // Reported again only after another grace period.
+        assert!(detector.check(15.0, &rates).is_empty());
+        assert_eq!(detector.check(20.0, &rates).len(), 1);
+    }
+}
@@ -0,0 +1,294 @@
+use crate::checkpoint;
+use crate::coordinator::{now_secs, Shared};
+use crate::heartbeat::Progress;
+use crate::shards;
+use crate::state::write_atomic;
+use crate::worker::{JobOutcome, WorkerState};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::{error, warn};
+
+/// How far back rates look.
+const RATE_WINDOW_SECS: f64 = 60.0;
+/// Samples closer together than this are not recorded.
+const SAMPLE_SPACING_SECS: f64 = 1.0;
+/// Shortest span a rate is computed over.
+const MIN_RATE_SPAN_SECS: f64 = 5.0;
+
+/// `checkpoint_dir/<job>/summary.json`.
+pub fn summary_path(checkpoint_dir: &Path, job_id: &str) -> PathBuf {
+    checkpoint_dir.join(job_id).join("summary.json")
+}
+
+/// Recent `(time, value)` samples of a counter that only grows, such as a
+/// rank's step. A counter going backwards (a restart from an older
+/// checkpoint) starts the window over.
+#[derive(Debug, Default)]
+pub struct RateWindow {
+    samples: VecDeque<(f64, u64)>,
+}
+
+impl RateWindow {
+    pub fn observe(&mut self, now: f64, value: u64) {
+        match self.samples.back() {
+            Some(&(_, last)) if value < last => self.samples.clear(),
+            Some(&(at, _)) if now - at < SAMPLE_SPACING_SECS => return,
+            _ => {}
+        }
+        self.samples.push_back((now, value));
+        while self.samples.front().is_some_and(|&(at, _)| at < now - RATE_WINDOW_SECS) {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Increase per second over the last minute, or `None` until enough
+    /// recent samples exist.
+    pub fn rate(&self, now: f64) -> Option<f64> {
+        let &(first_at, first) = self.samples.iter().find(|&&(at, _)| at >= now - RATE_WINDOW_SECS)?;
+        let &(last_at, last) = self.samples.back()?;
+        let span = last_at - first_at;
+        (span >= MIN_RATE_SPAN_SECS).then(|| (last - first) as f64 / span)
+    }
+}
+
+/// What `summary.json` holds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobSummary {
+    pub job_id: String,
+    /// Unix time the summary was computed.
+    pub updated_at: f64,
+    pub uptime_secs: f64,
+    pub world_size: usize,
+    pub generation: u64,
+    /// Set once every rank is terminal or the job was shut down.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outcome: Option<JobOutcome>,
+    /// Dataset lines processed, as of each rank's latest checkpoint, or of
+    /// the lease queue when shards are leased. Needs a shard manifest.
+    pub samples_done: Option<u64>,
+    /// Dataset lines times epochs.
+    pub samples_total: Option<u64>,
+    pub percent_complete: Option<f64>,
+    /// Sum of the running ranks' step rates.
+    pub steps_per_sec: Option<f64>,
+    pub samples_per_sec: Option<f64>,
+    pub eta_secs: Option<f64>,
+    pub ranks: Vec<RankSummary>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RankSummary {
+    pub rank: usize,
+    pub state: WorkerState,
+    /// Heartbeat step, or the `LATEST` checkpoint step without heartbeats.
+    pub step: Option<u64>,
+    pub steps_per_sec: Option<f64>,
+    /// Heartbeat loss, or the last loss recorded in the latest checkpoint.
+    pub loss: Option<f64>,
+    pub last_checkpoint_step: Option<u64>,
+}
+
+/// Computes job summaries and remembers what rates need between them.
+#[derive(Debug, Default)]
+pub struct Summarizer {
+    samples: RateWindow,
+    outcome: Option<JobOutcome>,
+}
+
+impl Summarizer {
+    pub fn collect(&mut self, shared: &Shared) -> JobSummary {
+        let now = now_secs();
+        let generation = shared.generation();
+        let checkpoint_dir = Path::new(&shared.config.checkpoint_dir);
+
+        let mut workers: Vec<(usize, WorkerState, Option<Progress>)> = shared
+            .workers()
+            .values()
+            .map(|w| (w.rank, w.state, w.progress.clone()))
+            .collect();
+        workers.sort_by_key(|&(rank, _, _)| rank);
+
+        let rates = shared.step_rates();
+        let mut ranks = Vec::with_capacity(workers.len());
+        let mut reported_samples_per_sec = None;
+        for (rank, state, progress) in workers {
+            let dir = checkpoint::worker_dir(checkpoint_dir, &shared.config.job_id, rank);
+            let checkpoint_step = checkpoint::latest_step(&dir);
+            let progress = progress.unwrap_or_default();
+            let running = state == WorkerState::Running;
+            if let (true, Some(rate)) = (running, progress.samples_per_sec) {
+                *reported_samples_per_sec.get_or_insert(0.0) += rate;
+            }
+            ranks.push(RankSummary {
+                rank,
+                state,
+                step: progress.step.or(checkpoint_step),
+                steps_per_sec: rates.get(&rank).filter(|_| running).and_then(|r| r.rate(now)),
+                loss: progress.loss.or_else(|| checkpoint_loss(&dir)),
+                last_checkpoint_step: progress.last_checkpoint_step.or(checkpoint_step),
+            });
+        }
+        drop(rates);
+
+        let (samples_done, samples_total) = samples(shared);
+        if let Some(done) = samples_done {
+            self.samples.observe(now, done);
+        }
+        let samples_per_sec = self.samples.rate(now).or(reported_samples_per_sec);
+        let remaining = samples_total.zip(samples_done).map(|(total, done)| total.saturating_sub(done));
+        let eta_secs = match (remaining, samples_per_sec) {
+            (Some(0), _) => Some(0.0),
+            (Some(remaining), Some(rate)) if rate > 0.0 && self.outcome.is_none() => Some(remaining as f64 / rate),
+            _ => None,
+        };
+        let steps: Vec<f64> = ranks.iter().filter_map(|r| r.steps_per_sec).collect();
+
+        JobSummary {
+            job_id: shared.config.job_id.clone(),
+            updated_at: now,
+            uptime_secs: now - shared.started_at,
+            world_size: generation.world_size,
+            generation: generation.number,
+            outcome: self.outcome,
+            samples_done,
+            samples_total,
+            percent_complete: samples_total
+                .zip(samples_done)
+                .filter(|&(total, _)| total > 0)
+                .map(|(total, done)| 100.0 * done as f64 / total as f64),
+            steps_per_sec: (!steps.is_empty()).then(|| steps.iter().sum()),
+            samples_per_sec,
+            eta_secs,
+            ranks,
+        }
+    }
+}
+
+/// Rewrites `summary.json` every `interval` until the coordinator exits.
+pub async fn run(shared: Arc<Shared>, interval: Duration) {
+    loop {
+        sleep(interval).await;
+        let shared = shared.clone();
+        let pass = tokio::task::spawn_blocking(move || write(&shared));
+        if let Err(e) = pass.await {
+            error!("[coord] job summary failed: {}", e);
+        }
+    }
+}
+
+/// Writes the final summary once the job is over.
+pub fn finish(shared: &Shared, outcome: JobOutcome) {
+    shared.summarizer().outcome = Some(outcome);
+    write(shared);
+}
+
+fn write(shared: &Shared) {
+    let path = summary_path(Path::new(&shared.config.checkpoint_dir), &shared.config.job_id);
+    // Held while writing so a periodic pass cannot overwrite the final one.
+    let mut summarizer = shared.summarizer();
+    let summary = summarizer.collect(shared);
+    let written = serde_json::to_vec_pretty(&summary)
+        .map_err(anyhow::Error::from)
+        .and_then(|json| write_atomic(&path, &json));
+    if let Err(e) = written {
+        warn!("[coord] failed to write {}: {:#}", path.display(), e);
+    }
+}
+
+/// `(done, total)` dataset lines. Lease progress counts whole epochs;
+/// otherwise progress comes from the ranks' checkpointed positions.
+fn samples(shared: &Shared) -> (Option<u64>, Option<u64>) {
+    if let Some(progress) = shared.lease_progress() {
+        let total = progress.lines_total * progress.epochs as u64;
+        let done = progress.epoch as u64 * progress.lines_total + progress.lines_done;
+        return (Some(done.min(total)), Some(total));
+    }
+    let manifest = shared.manifest.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    let Some(manifest) = manifest else {
+        return (None, None);
+    };
+    let positions = shards::checkpoint_positions(&shared.config, &manifest);
+    let done = manifest.progress(&positions).values().sum();
+    (Some(done), Some(manifest.total_lines()))
+}
+
+/// Last entry of the loss history kept in the rank's latest checkpoint.
+fn checkpoint_loss(worker_dir: &Path) -> Option<f64> {
+    let state = checkpoint::latest_state(worker_dir)?;
+    state.get("model_state")?.get("loss_history")?.as_array()?.last()?.as_f64()
+}
+
+/// `coordinator status`: the job's last `summary.json`, as a table or raw.
+pub fn print(checkpoint_dir: &Path, job_id: &str, json: bool) -> Result<()> {
+    let path = summary_path(checkpoint_dir, job_id);
+    let text = std::fs::read_to_string(&path).with_context(|| format!("failed to read {}", path.display()))?;
+    if json {
+        print!("{}", text);
+        return Ok(());
+    }
+    let summary: JobSummary =
+        serde_json::from_str(&text).with_context(|| format!("failed to parse {}", path.display()))?;
+
+    let status = summary.outcome.map_or("running".to_string(), |o| format!("finished ({})", o));
+    println!(
+        "job {}  {}  world_size={} generation={} uptime={}",
+        summary.job_id,
+        status,
+        summary.world_size,
+        summary.generation,
+        format_duration(summary.uptime_secs)
+    );
+    let progress = match (summary.samples_done, summary.samples_total) {
+        (Some(done), Some(total)) => format!(
+            "{}/{} samples ({:.1}%)",
+            done,
+            total,
+            summary.percent_complete.unwrap_or_default()
+        ),
+        _ => "progress unknown".to_string(),
+    };
+    println!(
+        "{}  {} samples/s  {} steps/s  eta {}",
+        progress,
+        format_rate(summary.samples_per_sec),
+        format_rate(summary.steps_per_sec),
+        summary.eta_secs.map_or("-".to_string(), format_duration)
+    );
+    let age = now_secs() - summary.updated_at;
+    if summary.outcome.is_none() && age > 60.0 {
+        println!("(summary is {} old; is the coordinator running?)", format_duration(age));
+    }
+    println!();
+    println!("{:>5}  {:<10}  {:>8}  {:>8}  {:>10}  {:>8}", "RANK", "STATE", "STEP", "STEPS/S", "LOSS", "CKPT");
+    let dash = |v: Option<u64>| v.map_or("-".to_string(), |v| v.to_string());
+    for r in &summary.ranks {
+        println!(
+            "{:>5}  {:<10}  {:>8}  {:>8}  {:>10}  {:>8}",
+            r.rank,
+            r.state.to_string(),
+            dash(r.step),
+            format_rate(r.steps_per_sec),
+            r.loss.map_or("-".to_string(), |l| format!("{:.4}", l)),
+            dash(r.last_checkpoint_step)
+        );
+    }
+    Ok(())
+}
+
+fn format_rate(rate: Option<f64>) -> String {
+    rate.map_or("-".to_string(), |r| format!("{:.2}", r))
+}
+
+fn format_duration(secs: f64) -> String {
+    let secs = secs.max(0.0) as u64;
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
@@ -0,0 +1,484 @@
+use crate::heartbeat::{Progress, WorkerHeartbeat};
+use crate::metrics::LifetimeHistogram;
+use crate::restart::RestartHistory;
+use crate::signals::{process_alive, send_signal};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{ExitCode, ExitStatus};
+use std::time::Instant;
+use tokio::process::Child;
+use tokio::task::JoinHandle;
+
+/// Lifecycle of a single rank as seen by the coordinator.
+///
+/// ```text
+/// Pending -> Running -> Succeeded
+///               |
+///               v
+///            Failed -> Running (restart)
+///               |
+///               v
+///           Exhausted
+/// ```
+///
+/// Any non-terminal state can also move to `Stopped` when the coordinator
+/// shuts the job down, or via `Draining` when an operator drains the rank.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkerState {
+    /// Not spawned yet, or its process is being (re)spawned.
+    Pending,
+    /// Process is alive and being monitored.
+    Running,
+    /// Process exited with status 0, meaning its training is complete;
+    /// never restarted.
+    Succeeded,
+    /// Process crashed, timed out, or failed to spawn; waiting for a restart.
+    Failed,
+    /// Restart budget used up; the rank is given up on.
+    Exhausted,
+    /// Asked to checkpoint and exit; becomes `Stopped` once it does.
+    Draining,
+    /// Stopped by the coordinator; never restarted.
+    Stopped,
+}
+
+impl WorkerState {
+    /// Terminal states are never left again.
+    pub fn is_terminal(self) -> bool {
+        matches!(
+            self,
+            WorkerState::Succeeded | WorkerState::Exhausted | WorkerState::Stopped
+        )
+    }
+}
+
+impl fmt::Display for WorkerState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            WorkerState::Pending => "pending",
+            WorkerState::Running => "running",
+            WorkerState::Succeeded => "succeeded",
+            WorkerState::Failed => "failed",
+            WorkerState::Exhausted => "exhausted",
+            WorkerState::Draining => "draining",
+            WorkerState::Stopped => "stopped",
+        };
+        f.write_str(s)
+    }
+}
+
+/// Why a rank last moved to `Failed`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureReason {
+    /// Process exited non-zero or was killed by a signal.
+    Exited {
+        code: Option<i32>,
+        signal: Option<i32>,
+    },
+    /// No heartbeat within `heartbeat_timeout`.
+    HeartbeatTimeout,
+    /// Heartbeating, but its step did not advance within `progress_timeout`.
+    Hung,
+    SpawnFailed,
+    /// `try_wait` on the process failed.
+    PollError,
+    /// Killed by an operator through the control API.
+    Killed,
+    /// Killed by the straggler policy for falling behind the other ranks.
+    Straggler,
+    /// An adopted process exited, and neither the lease queue nor its last
+    /// checkpoint shows it finished. Its exit status is unknown, so it does
+    /// not count as a crash.
+    AdoptedExit,
+}
+
+impl FailureReason {
+    pub fn exited(exit: ExitInfo) -> Self {
+        FailureReason::Exited {
+            code: exit.code,
+            signal: exit.signal,
+        }
+    }
+
+    /// The process exited on its own, other than on a SIGTERM from outside
+    /// the coordinator; only these count toward the crash-loop breaker.
+    pub fn is_crash(self) -> bool {
+        match self {
+            FailureReason::Exited { code, signal } => !ExitInfo { code, signal }.stopped(),
+            _ => false,
+        }
+    }
+
+    /// Short, stable name for logs and metrics labels.
+    pub fn label(self) -> &'static str {
+        match self {
+            FailureReason::Exited { .. } => "exited",
+            FailureReason::HeartbeatTimeout => "heartbeat_timeout",
+            FailureReason::Hung => "hung",
+            FailureReason::SpawnFailed => "spawn_failed",
+            FailureReason::PollError => "poll_error",
+            FailureReason::Killed => "killed",
+            FailureReason::Straggler => "straggler",
+            FailureReason::AdoptedExit => "adopted_exit",
+        }
+    }
+}
+
+impl fmt::Display for FailureReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FailureReason::Exited { code: Some(code), .. } => write!(f, "exited with code {}", code),
+            FailureReason::Exited { signal: Some(sig), .. } => write!(f, "killed by signal {}", sig),
+            other => f.write_str(other.label()),
+        }
+    }
+}
+
+/// Exit code of a worker that checkpointed and exited on SIGTERM before
+/// finishing its work (128 + SIGTERM). Exit 0 is reserved for a worker
+/// whose training is complete.
+pub const STOPPED_EXIT_CODE: i32 = 143;
+
+/// How a worker process ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExitInfo {
+    pub code: Option<i32>,
+    pub signal: Option<i32>,
+}
+
+impl ExitInfo {
+    /// Exit of an adopted process, whose status cannot be collected.
+    pub const UNKNOWN: ExitInfo = ExitInfo { code: None, signal: None };
+
+    pub fn success(self) -> bool {
+        self.code == Some(0)
+    }
+
+    /// Stopped by SIGTERM: exited with [`STOPPED_EXIT_CODE`] after
+    /// checkpointing, or killed by the signal itself.
+    pub fn stopped(self) -> bool {
+        self.code == Some(STOPPED_EXIT_CODE) || self.signal == Some(libc::SIGTERM)
+    }
+}
+
+impl From<ExitStatus> for ExitInfo {
+    fn from(status: ExitStatus) -> Self {
+        ExitInfo {
+            code: status.code(),
+            signal: status.signal(),
+        }
+    }
+}
+
+impl fmt::Display for ExitInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.code, self.signal) {
+            (Some(code), _) => write!(f, "exit code {}", code),
+            (None, Some(sig)) => write!(f, "signal {}", sig),
+            (None, None) => f.write_str("unknown status"),
+        }
+    }
+}
+
+/// A rank's OS process.
+#[derive(Debug)]
+pub enum WorkerProcess {
+    /// Spawned by this coordinator.
+    Spawned(Child),
+    /// Left running by a previous coordinator and adopted on resume. It is
+    /// not our child, so only its liveness can be observed.
+    Adopted(u32),
+}
+
+impl WorkerProcess {
+    pub fn id(&self) -> Option<u32> {
+        match self {
+            WorkerProcess::Spawned(child) => child.id(),
+            WorkerProcess::Adopted(pid) => Some(*pid),
+        }
+    }
+
+    /// Returns how the process ended, or `None` while it is still running.
+    pub fn try_wait(&mut self) -> io::Result<Option<ExitInfo>> {
+        match self {
+            WorkerProcess::Spawned(child) => Ok(child.try_wait()?.map(ExitInfo::from)),
+            WorkerProcess::Adopted(pid) => Ok((!process_alive(*pid)).then_some(ExitInfo::UNKNOWN)),
+        }
+    }
+
+    /// Sends SIGKILL without waiting.
+    pub fn start_kill(&mut self) -> io::Result<()> {
+        match self {
+            WorkerProcess::Spawned(child) => child.start_kill(),
+            WorkerProcess::Adopted(pid) => send_signal(*pid, libc::SIGKILL),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Worker {
+    pub rank: usize,
+    pub state: WorkerState,
+    pub process: Option<WorkerProcess>,
+    pub restarts: usize,
+    /// Restarts by the [`FailureReason::label`] that caused them, plus
+    /// `requested` for operator restarts.
+    pub restart_reasons: BTreeMap<String, u64>,
+    /// When the current process was spawned.
+    pub started_at: f64,
+    /// How long each finished process of this rank ran.
+    pub lifetimes: LifetimeHistogram,
+    pub last_heartbeat: f64,
+    /// Latest progress reported by any incarnation of this rank.
+    pub progress: Option<Progress>,
+    /// Last observed (heartbeat step, LATEST checkpoint step) pair.
+    pub last_steps: Option<(Option<u64>, Option<u64>)>,
+    /// When `last_steps` last changed (or the process started).
+    pub step_advanced_at: f64,
+    /// When a stack dump was requested from a seemingly hung process.
+    pub dump_requested_at: Option<f64>,
+    pub last_failure: Option<FailureReason>,
+    /// When a `Draining` worker gets killed if it has not exited.
+    pub drain_deadline: Option<f64>,
+    /// When a `Failed` worker is due to be restarted; `None` until the
+    /// restart policy has looked at the failure.
+    pub restart_at: Option<Instant>,
+    /// Timer task that performs the scheduled restart.
+    pub restart_task: Option<JoinHandle<()>>,
+    pub history: RestartHistory,
+}
+
+impl Worker {
+    pub fn new(rank: usize) -> Self {
+        Self {
+            rank,
+            state: WorkerState::Pending,
+            process: None,
+            restarts: 0,
+            restart_reasons: BTreeMap::new(),
+            started_at: 0.0,
+            lifetimes: LifetimeHistogram::default(),
+            last_heartbeat: 0.0,
+            progress: None,
+            last_steps: None,
+            step_advanced_at: 0.0,
+            dump_requested_at: None,
+            last_failure: None,
+            drain_deadline: None,
+            restart_at: None,
+            restart_task: None,
+            history: RestartHistory::default(),
+        }
+    }
+
+    /// Installs a freshly spawned process and marks the worker `Running`.
+    pub fn start(&mut self, child: Child) {
+        self.run(WorkerProcess::Spawned(child), crate::coordinator::now_secs());
+    }
+
+    /// Takes over a process a previous coordinator started at `started_at`.
+    pub fn adopt(&mut self, pid: u32, started_at: f64) {
+        self.run(WorkerProcess::Adopted(pid), started_at);
+    }
+
+    fn run(&mut self, process: WorkerProcess, started_at: f64) {
+        self.process = Some(process);
+        self.state = WorkerState::Running;
+        let now = crate::coordinator::now_secs();
+        self.started_at = started_at;
+        self.last_heartbeat = now;
+        self.last_steps = None;
+        self.step_advanced_at = now;
+        self.dump_requested_at = None;
+        self.history.on_start(Instant::now());
+    }
+
+    /// Kills the process, if any, and marks the worker `Failed`.
+    pub fn fail(&mut self, reason: FailureReason) {
+        self.kill();
+        self.state = WorkerState::Failed;
+        self.last_failure = Some(reason);
+    }
+
+    /// Records a heartbeat from the current process; `seen_at` is the
+    /// liveness time to credit it with.
+    pub fn observe_heartbeat(&mut self, hb: &WorkerHeartbeat, seen_at: f64) {
+        self.last_heartbeat = self.last_heartbeat.max(seen_at);
+        // Version 1 heartbeats carry no progress; keep what we had.
+        if hb.version >= 2 {
+            self.progress = Some(hb.progress.clone());
+        }
+    }
+
+    /// Point-in-time view for status reporting.
+    pub fn status(&self, now: f64) -> WorkerStatus {
+        let last_heartbeat = (self.process.is_some() && self.last_heartbeat > 0.0)
+            .then_some(self.last_heartbeat);
+        WorkerStatus {
+            rank: self.rank,
+            state: self.state,
+            pid: self.pid(),
+            restarts: self.restarts,
+            last_heartbeat,
+            heartbeat_age_secs: last_heartbeat.map(|t| (now - t).max(0.0)),
+            step: self.progress.as_ref().and_then(|p| p.step),
+            progress: self.progress.clone(),
+            last_failure: self.last_failure.map(|r| r.to_string()),
+            restart_in_secs: self
+                .restart_at
+                .map(|at| at.saturating_duration_since(Instant::now()).as_secs_f64()),
+        }
+    }
+
+    /// PID of the current process, if one is running.
+    pub fn pid(&self) -> Option<u32> {
+        self.process.as_ref().and_then(|p| p.id())
+    }
+
+    /// Forgets a process that has exited, recording how long it ran.
+    pub fn reaped(&mut self) {
+        if self.process.take().is_some() {
+            self.lifetimes
+                .observe((crate::coordinator::now_secs() - self.started_at).max(0.0));
+        }
+    }
+
+    /// Sends SIGKILL without waiting; tokio reaps the dropped process.
+    pub fn kill(&mut self) {
+        if let Some(process) = self.process.as_mut() {
+            let _ = process.start_kill();
+        }
+        self.reaped();
+    }
+
+    pub fn record_restart(&mut self, reason: &str) {
+        *self.restart_reasons.entry(reason.to_string()).or_default() += 1;
+    }
+}
+
+/// Serializable snapshot of a [`Worker`].
+#[derive(Debug, Clone, Serialize)]
+pub struct WorkerStatus {
+    pub rank: usize,
+    pub state: WorkerState,
+    pub pid: Option<u32>,
+    pub restarts: usize,
+    /// Unix time of the last heartbeat credited to the current process.
+    pub last_heartbeat: Option<f64>,
+    pub heartbeat_age_secs: Option<f64>,
+    pub step: Option<u64>,
+    pub progress: Option<Progress>,
+    pub last_failure: Option<String>,
+    /// Time until a scheduled restart fires.
+    pub restart_in_secs: Option<f64>,
+}
+
+/// Final result of a job once every rank reached a terminal state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobOutcome {
+    /// Every rank succeeded.
+    Success,
+    /// Some ranks succeeded, others exhausted their restarts.
+    PartialFailure,
+    /// No rank succeeded.
+    Exhausted,
+    /// Shut down; every worker exited cleanly within the grace period.
+    Stopped,
+    /// Shut down; some workers had to be killed after the grace period.
+    StopTimedOut,
+    /// Shut down; some workers exited with an error while draining.
+    StopFailed,
+}
+
+impl JobOutcome {
+    pub fn from_states<I: IntoIterator<Item = WorkerState>>(states: I) -> Self {
+        let (mut succeeded, mut exhausted, mut total) = (0, 0, 0);
+        for state in states {
+            total += 1;
+            match state {
+                WorkerState::Succeeded => succeeded += 1,
+                WorkerState::Exhausted => exhausted += 1,
+                _ => {}
+            }
+        }
+        if succeeded == total {
+            JobOutcome::Success
+        } else if exhausted == 0 {
+            // The remaining ranks were drained by an operator.
+            JobOutcome::Stopped
+        } else if succeeded == 0 {
+            JobOutcome::Exhausted
+        } else {
+            JobOutcome::PartialFailure
+        }
+    }
+
+    /// Shut down before every rank reached an end.
+    pub fn is_stop(self) -> bool {
+        matches!(self, JobOutcome::Stopped | JobOutcome::StopTimedOut | JobOutcome::StopFailed)
+    }
+
+    pub fn exit_code(self) -> ExitCode {
+        match self {
+            JobOutcome::Success => ExitCode::SUCCESS,
+            JobOutcome::PartialFailure => ExitCode::from(2),
+            JobOutcome::Exhausted => ExitCode::from(3),
+            JobOutcome::Stopped => ExitCode::from(4),
+            JobOutcome::StopTimedOut => ExitCode::from(5),
+            JobOutcome::StopFailed => ExitCode::from(6),
+        }
+    }
+}
+
+impl fmt::Display for JobOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            JobOutcome::Success => "success",
+            JobOutcome::PartialFailure => "partial_failure",
+            JobOutcome::Exhausted => "exhausted",
+            JobOutcome::Stopped => "stopped",
+            JobOutcome::StopTimedOut => "stop_timed_out",
+            JobOutcome::StopFailed => "stop_failed",
+        };
+        f.write_str(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use WorkerState::*;
+
+    #[test]
+    fn outcome_is_success_only_if_every_rank_succeeded() {
+        assert_eq!(JobOutcome::from_states([Succeeded, Succeeded]), JobOutcome::Success);
+        assert_eq!(JobOutcome::from_states([Succeeded, Exhausted]), JobOutcome::PartialFailure);
+        assert_eq!(JobOutcome::from_states([Exhausted, Exhausted]), JobOutcome::Exhausted);
+    }
+
+    #[test]
+    fn drained_ranks_make_the_job_stopped() {
+        assert_eq!(JobOutcome::from_states([Succeeded, Stopped]), JobOutcome::Stopped);
+        assert_eq!(JobOutcome::from_states([Stopped, Stopped]), JobOutcome::Stopped);
+        // An exhausted rank outweighs the drained ones.
+        assert_eq!(JobOutcome::from_states([Stopped, Exhausted]), JobOutcome::Exhausted);
+        assert_eq!(JobOutcome::from_states([Succeeded, Stopped, Exhausted]), JobOutcome::PartialFailure);
+    }
+
+    #[test]
+    fn stops_and_adopted_exits_are_not_crashes() {
+        assert!(FailureReason::Exited { code: Some(1), signal: None }.is_crash());
+        assert!(!FailureReason::exited(ExitInfo { code: Some(STOPPED_EXIT_CODE), signal: None }).is_crash());
+        assert!(!FailureReason::AdoptedExit.is_crash());
+    }
+
+    #[test]
+    fn no_ranks_is_success() {
+        assert_eq!(JobOutcome::from_states([]), JobOutcome::Success);
+    }
+}
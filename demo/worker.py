@@ -1,8 +1,15 @@
 import os
+import sys
 import json
 import time
+import signal
+import socket
 import shutil
 import threading
+import hashlib
+import faulthandler
+import urllib.error
+import urllib.request
 from pathlib import Path
 from itertools import islice
 
@@ -20,17 +27,92 @@ from demo.training import FakeModel, FakeOptimizer, train_step
 JOB_ID = os.getenv("JOB_ID", "demo-job")
 RANK = int(os.getenv("RANK", "0"))
 WORLD_SIZE = int(os.getenv("WORLD_SIZE", "1"))
+# Bumped by the coordinator whenever the world size changes
+GENERATION = int(os.getenv("GENERATION", "0"))
 
 CHECKPOINT_DIR = Path(os.getenv("CHECKPOINT_DIR", "./checkpoints"))
 CHECKPOINT_EVERY = int(os.getenv("CHECKPOINT_EVERY", "5"))
 SLEEP_SEC = float(os.getenv("SLEEP_SEC", "0.5"))
 
 DATASET_DIR = Path(os.getenv("DATASET_DIR", "./data/shards"))
+# Shard assignment written by the coordinator (local datasets only)
+SHARD_MANIFEST_PATH = os.getenv("SHARD_MANIFEST_PATH")
+
+# Push heartbeats to the coordinator's listener when it advertises one
+HEARTBEAT_URL = os.getenv("HEARTBEAT_URL")
+HEARTBEAT_UDP = os.getenv("HEARTBEAT_UDP")  # host:port
+# Lease work items from the coordinator instead of a fixed shard list
+LEASE_URL = os.getenv("LEASE_URL")
+# Step of this rank in the last global checkpoint, to restart from
+RESUME_STEP = os.getenv("RESUME_STEP")
 # ----------------------------
 
 JOB_DIR = CHECKPOINT_DIR / JOB_ID / f"worker_{RANK}"
 LATEST_FILE = JOB_DIR / "LATEST"
 HEARTBEAT_FILE = JOB_DIR / "HEARTBEAT"
+# Global checkpoint barrier announced by the coordinator, and our answer
+BARRIER_FILE = CHECKPOINT_DIR / JOB_ID / "BARRIER"
+BARRIER_ACK_FILE = JOB_DIR / "BARRIER_ACK"
+
+HEARTBEAT_VERSION = 2
+
+# Training progress shared with the heartbeat thread
+PROGRESS = {}
+PROGRESS_LOCK = threading.Lock()
+
+
+class _PipeSafeStream:
+    """Line-buffered stream that keeps working if the coordinator reading our
+    piped output dies, so the worker can still checkpoint on the SIGTERM of
+    the coordinator that replaces it."""
+
+    def __init__(self, stream):
+        self._stream = stream
+
+    def write(self, s):
+        try:
+            n = self._stream.write(s)
+            if "\n" in s:
+                self._stream.flush()
+            return n
+        except (BrokenPipeError, OSError):
+            return len(s)
+
+    def flush(self):
+        try:
+            self._stream.flush()
+        except (BrokenPipeError, OSError):
+            pass
+
+    def __getattr__(self, name):
+        return getattr(self._stream, name)
+
+
+sys.stdout = _PipeSafeStream(sys.stdout)
+sys.stderr = _PipeSafeStream(sys.stderr)
+
+
+# Set by SIGTERM: checkpoint at the next step boundary and exit cleanly
+STOP_REQUESTED = threading.Event()
+
+# Set once the coordinator reports every leased epoch done
+EPOCHS_DONE = threading.Event()
+
+# Exit code after stopping on SIGTERM before the work is done (128 + SIGTERM).
+# Exit 0 tells the coordinator this rank finished training.
+STOPPED_EXIT_CODE = 143
+
+# Last global checkpoint barrier this process took part in
+BARRIER_JOINED = None
+
+
+def _request_stop(signum, frame):
+    STOP_REQUESTED.set()
+
+
+def update_progress(**fields):
+    with PROGRESS_LOCK:
+        PROGRESS.update(fields)
 
 
 def load_checkpoint():
@@ -39,12 +121,15 @@ def load_checkpoint():
             "step": 0,
             "rank": RANK,
             "world_size": WORLD_SIZE,
+            "generation": GENERATION,
             "shard_idx": 0,
             "line_idx": 0,
             "model_state": None,
         }
 
     step_dir = LATEST_FILE.read_text().strip()
+    if RESUME_STEP is not None and step_dir != f"step_{RESUME_STEP}":
+        step_dir = rollback_to(int(RESUME_STEP), step_dir)
     state_path = JOB_DIR / step_dir / "state.json"
 
     if not state_path.exists():
@@ -60,10 +145,40 @@ def load_checkpoint():
     state.setdefault("shard_idx", 0)
     state.setdefault("line_idx", 0)
     state.setdefault("model_state", None)
+    state.setdefault("generation", 0)
+
+    if state["generation"] != GENERATION:
+        # The world size changed and shards were reassigned; the manifest's
+        # offsets already account for what this checkpoint processed.
+        if not LEASE_URL:
+            print(
+                f"[worker {RANK}] checkpoint is from generation {state['generation']}; "
+                f"starting generation {GENERATION} on the new assignment"
+            )
+            state["shard_idx"] = 0
+            state["line_idx"] = 0
+        state["generation"] = GENERATION
+        state["world_size"] = WORLD_SIZE
 
     return state
 
 
+def rollback_to(step: int, latest: str) -> str:
+    """Makes the global checkpoint's step this rank's latest one, dropping
+    checkpoints past it so they are never picked up again."""
+    target = JOB_DIR / f"step_{step}"
+    if not target.exists():
+        print(f"[worker {RANK}] global checkpoint step_{step} is missing; resuming from {latest}")
+        return latest
+    print(f"[worker {RANK}] rolling back from {latest} to global checkpoint step_{step}")
+    for d in JOB_DIR.glob("step_*"):
+        suffix = d.name[len("step_"):]
+        if suffix.isdigit() and int(suffix) > step:
+            _safe_rmtree(d)
+    LATEST_FILE.write_text(target.name)
+    return target.name
+
+
 def _safe_rmtree(p: Path):
     try:
         if p.exists():
@@ -118,15 +233,84 @@ def save_checkpoint(state):
     LATEST_FILE.write_text(final_dir.name)
 
 
+def _read_barrier():
+    try:
+        with open(BARRIER_FILE, "r", encoding="utf-8") as f:
+            return json.load(f)
+    except (OSError, ValueError):
+        return None
+
+
+def join_barrier(state, model):
+    """Takes part in a global checkpoint the coordinator announced, if any:
+    commits a checkpoint at this step boundary, acknowledges it, and waits
+    until the coordinator has heard from every rank, so none runs ahead."""
+    global BARRIER_JOINED
+    barrier = _read_barrier()
+    if not barrier or barrier.get("phase") != "pending" or barrier["epoch"] == BARRIER_JOINED:
+        return
+    epoch = BARRIER_JOINED = barrier["epoch"]
+
+    state["model_state"] = model.state_dict()
+    print(f"[worker {RANK}] global checkpoint {epoch}: checkpointing at step {state['step']}")
+    save_checkpoint(state)
+    update_progress(last_checkpoint_step=state["step"])
+    tmp = BARRIER_ACK_FILE.with_suffix(".tmp")
+    tmp.write_text(json.dumps({"epoch": epoch, "step": state["step"]}))
+    os.replace(tmp, BARRIER_ACK_FILE)
+
+    while not STOP_REQUESTED.is_set():
+        barrier = _read_barrier()
+        if not barrier or barrier["epoch"] != epoch or barrier["phase"] != "pending":
+            break
+        if time.time() > barrier["deadline"] + 5.0:
+            # The coordinator is gone; carry on rather than wait forever.
+            break
+        STOP_REQUESTED.wait(0.2)
+    if barrier and barrier["epoch"] == epoch and barrier["phase"] != "pending":
+        print(f"[worker {RANK}] global checkpoint {epoch} {barrier['phase']}")
+
+
+def _sha256(path: Path) -> str:
+    digest = hashlib.sha256()
+    with open(path, "rb") as f:
+        for block in iter(lambda: f.read(1 << 16), b""):
+            digest.update(block)
+    return digest.hexdigest()
+
+
+def manifest_shards(manifest_path: Path):
+    """Shards the coordinator assigned to this rank, checked against the
+    manifest's checksums so a modified shard is never trained on silently,
+    and the line each one starts at."""
+    with open(manifest_path, "r", encoding="utf-8") as f:
+        manifest = json.load(f)
+    if manifest.get("world_size") != WORLD_SIZE:
+        raise RuntimeError(
+            f"shard manifest is for world_size={manifest.get('world_size')}, not {WORLD_SIZE}"
+        )
+    info = {s["name"]: s for s in manifest["shards"]}
+    shards = []
+    for name in manifest["assignment"].get(str(RANK), []):
+        path = DATASET_DIR / name
+        if _sha256(path) != info[name]["sha256"]:
+            raise RuntimeError(f"shard {name} does not match its checksum in {manifest_path}")
+        shards.append(path)
+    return shards, manifest.get("offsets", {})
+
+
 def assigned_shards():
-    """Get list of shards assigned to this worker (round-robin by rank)."""
+    """Get list of shards assigned to this worker (round-robin by rank), and
+    the line each shard starts at if not the first."""
     if HAS_S3 and use_s3():
         # Use S3
         bucket = os.getenv("S3_BUCKET", "training-data")
         shards = list_shards(bucket, prefix="shards/")
         # Filter by rank
         assigned = [s for i, s in enumerate(shards) if i % WORLD_SIZE == RANK]
-        return assigned
+        return assigned, {}
+    elif SHARD_MANIFEST_PATH:
+        return manifest_shards(Path(SHARD_MANIFEST_PATH))
     else:
         # Use local filesystem
         all_shards = sorted(DATASET_DIR.glob("shard_*.txt"))
@@ -134,49 +318,224 @@ def assigned_shards():
         def shard_index(path: Path) -> int:
             return int(path.stem.split("_")[1])
 
-        return [p for p in all_shards if shard_index(p) % WORLD_SIZE == RANK]
+        return [p for p in all_shards if shard_index(p) % WORLD_SIZE == RANK], {}
+
+
+def _lease_call(path: str, **fields):
+    body = json.dumps({"rank": RANK, "pid": os.getpid(), **fields}).encode("utf-8")
+    req = urllib.request.Request(
+        LEASE_URL + path, data=body, headers={"Content-Type": "application/json"}, method="POST"
+    )
+    with urllib.request.urlopen(req, timeout=5.0) as resp:
+        data = resp.read()
+    return json.loads(data) if data else None
+
+
+def leased_items():
+    """Work items leased from the coordinator, until every epoch is done."""
+    while not STOP_REQUESTED.is_set():
+        try:
+            reply = _lease_call("/lease")
+        except Exception as e:
+            print(f"[worker {RANK}] lease request failed: {e}")
+            STOP_REQUESTED.wait(1.0)
+            continue
+        if reply["status"] == "done":
+            EPOCHS_DONE.set()
+            return
+        if reply["status"] == "wait":
+            STOP_REQUESTED.wait(reply.get("retry_after_secs", 1.0))
+            # Lets the caller join a barrier while idle.
+            yield None
+            continue
+        yield reply
+
+
+def renew_lease(lease_id: int, step: int) -> bool:
+    """Reports progress on a lease. False if it was taken away from us."""
+    try:
+        _lease_call(f"/lease/{lease_id}/renew", step=step)
+    except urllib.error.HTTPError as e:
+        if e.code == 409:
+            return False
+        print(f"[worker {RANK}] lease {lease_id} renewal failed: {e.code}")
+    except Exception as e:
+        print(f"[worker {RANK}] lease {lease_id} renewal failed: {e}")
+    return True
+
+
+def complete_lease(lease_id: int) -> bool:
+    try:
+        _lease_call(f"/lease/{lease_id}/complete")
+        return True
+    except urllib.error.HTTPError as e:
+        # Expired and handed to another rank; its lines will be redone there.
+        print(f"[worker {RANK}] lease {lease_id} not accepted: {e.code} {e.read().decode(errors='replace')}")
+        return False
+
+
+def _push_heartbeat(payload: bytes, udp_sock, udp_addr):
+    if HEARTBEAT_URL:
+        req = urllib.request.Request(
+            HEARTBEAT_URL, data=payload, headers={"Content-Type": "application/json"}, method="POST"
+        )
+        try:
+            urllib.request.urlopen(req, timeout=1.0).close()
+        except Exception:
+            pass
+    if udp_sock is not None:
+        try:
+            udp_sock.sendto(payload, udp_addr)
+        except Exception:
+            pass
 
 
 def heartbeat_loop():
-    """Write heartbeat file every 2 seconds so coordinator knows we're alive."""
+    """Write heartbeat file (and push it, if configured) every 2 seconds so coordinator knows we're alive."""
+    udp_sock, udp_addr = None, None
+    if HEARTBEAT_UDP:
+        host, _, port = HEARTBEAT_UDP.rpartition(":")
+        udp_addr = (host, int(port))
+        udp_sock = socket.socket(socket.AF_INET, socket.SOCK_DGRAM)
+
+    last_step, last_time = None, time.time()
     while True:
+        now = time.time()
+        with PROGRESS_LOCK:
+            progress = dict(PROGRESS)
+        step = progress.get("step")
+        if step is not None and last_step is not None and now > last_time:
+            # One sample (line) per step
+            progress["samples_per_sec"] = (step - last_step) / (now - last_time)
+        last_step, last_time = step, now
+
+        payload = json.dumps(
+            {"version": HEARTBEAT_VERSION, "timestamp": now, "rank": RANK, "pid": os.getpid(), **progress}
+        )
         try:
             HEARTBEAT_FILE.parent.mkdir(parents=True, exist_ok=True)
-            HEARTBEAT_FILE.write_text(json.dumps({"timestamp": time.time(), "rank": RANK, "pid": os.getpid()}))
+            HEARTBEAT_FILE.write_text(payload)
         except Exception:
             pass
+        _push_heartbeat(payload.encode("utf-8"), udp_sock, udp_addr)
         time.sleep(2.0)
 
 
+def train_leased(state, model, optimizer):
+    """Processes leased work items until the coordinator reports all epochs done.
+
+    The checkpoint records the lease being worked on, so a restarted worker
+    that gets the same lease back resumes mid-item. Returns False if SIGTERM
+    stopped it first.
+    """
+    for lease in leased_items():
+        if lease is None:
+            join_barrier(state, model)
+            continue
+        lease_id = lease["lease_id"]
+        start = lease["start_line"]
+        if state.get("lease_id") == lease_id:
+            start = max(start, int(state["line_idx"]))
+        state["lease_id"] = lease_id
+        state["line_idx"] = start
+        print(
+            f"[worker {RANK}] lease {lease_id}: {lease['shard']} lines "
+            f"{start}..{lease['end_line']} (epoch {lease['epoch'] + 1})"
+        )
+        # Renew well within the timeout; renewals also tell us if we lost it
+        renew_every = max(1.0, lease["timeout_secs"] / 4)
+        renewed_at = time.time()
+        lost = False
+
+        with open(lease["path"], "r", encoding="utf-8") as f:
+            for line in islice(f, start, lease["end_line"]):
+                if time.time() - renewed_at >= renew_every:
+                    renewed_at = time.time()
+                    if not renew_lease(lease_id, state["step"]):
+                        print(f"[worker {RANK}] lease {lease_id} was taken away; dropping it")
+                        lost = True
+                        break
+                join_barrier(state, model)
+                if STOP_REQUESTED.wait(SLEEP_SEC):
+                    state["model_state"] = model.state_dict()
+                    print(f"[worker {RANK}] stop requested; checkpointing at step {state['step']}")
+                    save_checkpoint(state)
+                    return False
+
+                state["step"] += 1
+                state["line_idx"] += 1
+                loss = train_step(model, optimizer, batch_size=32)
+                update_progress(step=state["step"], line_idx=state["line_idx"], loss=loss)
+                print(
+                    f"[worker {RANK}] step {state['step']} | loss {loss:.4f} | {line.strip()} "
+                    f"| (lease={lease_id} li={state['line_idx']})"
+                )
+
+                if state["step"] % CHECKPOINT_EVERY == 0:
+                    state["model_state"] = model.state_dict()
+                    print(f"[worker {RANK}] checkpointing at step {state['step']} (loss: {loss:.4f})")
+                    save_checkpoint(state)
+                    update_progress(last_checkpoint_step=state["step"])
+
+        if lost:
+            continue
+
+        # Make the item's training durable before reporting it done.
+        state["model_state"] = model.state_dict()
+        save_checkpoint(state)
+        update_progress(last_checkpoint_step=state["step"])
+        complete_lease(lease_id)
+
+    if EPOCHS_DONE.is_set():
+        print(f"[worker {RANK}] all epochs done. Exiting.")
+    return EPOCHS_DONE.is_set()
+
+
 def main():
+    """Trains until the work is done or SIGTERM stops it. Returns whether the
+    work is done."""
     JOB_DIR.mkdir(parents=True, exist_ok=True)
 
+    # Dump all thread stacks to stderr when the coordinator suspects a hang
+    if hasattr(signal, "SIGUSR1"):
+        faulthandler.register(signal.SIGUSR1, all_threads=True)
+    signal.signal(signal.SIGTERM, _request_stop)
+
     # Start heartbeat thread
     hb_thread = threading.Thread(target=heartbeat_loop, daemon=True)
     hb_thread.start()
 
     state = load_checkpoint()
     print(f"[worker {RANK}] starting from step {state['step']}")
+    update_progress(
+        step=state["step"],
+        shard_idx=state["shard_idx"],
+        line_idx=state["line_idx"],
+        last_checkpoint_step=state["step"] if LATEST_FILE.exists() else None,
+    )
 
-    shards = assigned_shards()
-    use_s3_flag = HAS_S3 and use_s3()
-    shard_names = [Path(s).name if isinstance(s, str) else s.name for s in shards]
-    print(f"[worker {RANK}] assigned {len(shards)} shard(s) {'(from S3)' if use_s3_flag else '(local)'}: {shard_names}")
-
-    if not shards:
-        print(f"[worker {RANK}] no shards assigned. Exiting.")
-        return
-    
     # Initialize model and optimizer
     model = FakeModel(input_size=10, hidden_size=64, output_size=1)
     optimizer = FakeOptimizer(model, learning_rate=0.001)
-    
+
     # Load model state from checkpoint if available
     if state.get("model_state"):
         print(f"[worker {RANK}] loading model state from checkpoint")
         model.load_state_dict(state["model_state"])
         print(f"[worker {RANK}] model loaded with {len(model.loss_history)} loss history entries")
 
+    if LEASE_URL:
+        return train_leased(state, model, optimizer)
+
+    shards, offsets = assigned_shards()
+    use_s3_flag = HAS_S3 and use_s3()
+    shard_names = [Path(s).name if isinstance(s, str) else s.name for s in shards]
+    print(f"[worker {RANK}] assigned {len(shards)} shard(s) {'(from S3)' if use_s3_flag else '(local)'}: {shard_names}")
+
+    if not shards:
+        print(f"[worker {RANK}] no shards assigned. Exiting.")
+        return True
+
     # Clamp resume values
     state["shard_idx"] = min(int(state.get("shard_idx", 0)), len(shards) - 1)
     state["line_idx"] = max(int(state.get("line_idx", 0)), 0)
@@ -202,14 +561,23 @@ def main():
         else:
             shard_path = shard
 
+        # line_idx counts from the start of the shard, including lines
+        # processed in earlier generations
+        offset = int(offsets.get(shard_path.name, 0))
         if si != state["shard_idx"]:
-            state["line_idx"] = 0
+            state["line_idx"] = offset
+        state["line_idx"] = max(int(state["line_idx"]), offset)
 
         start_line = int(state["line_idx"])
 
         with shard_path.open("r", encoding="utf-8") as f:
             for line in islice(f, start_line, None):
-                time.sleep(SLEEP_SEC)
+                join_barrier(state, model)
+                if STOP_REQUESTED.wait(SLEEP_SEC):
+                    state["model_state"] = model.state_dict()
+                    print(f"[worker {RANK}] stop requested; checkpointing at step {state['step']}")
+                    save_checkpoint(state)
+                    return False
 
                 state["step"] += 1
                 state["shard_idx"] = si
@@ -219,6 +587,9 @@ def main():
                 
                 # Execute ML training step
                 loss = train_step(model, optimizer, batch_size=32)
+                update_progress(
+                    step=state["step"], shard_idx=si, line_idx=state["line_idx"], loss=loss
+                )
                 
                 print(
                     f"[worker {RANK}] step {state['step']} | loss {loss:.4f} | {sample} "
@@ -230,10 +601,17 @@ def main():
                     state["model_state"] = model.state_dict()
                     print(f"[worker {RANK}] checkpointing at step {state['step']} (loss: {loss:.4f})")
                     save_checkpoint(state)
+                    update_progress(last_checkpoint_step=state["step"])
 
+    # Record exactly how far we got, for remapping after a world size change
+    state["model_state"] = model.state_dict()
+    save_checkpoint(state)
     print(f"[worker {RANK}] finished all assigned shards. Exiting.")
+    return True
 
 
 
 if __name__ == "__main__":
-    main()
+    # A SIGTERM that arrives after the last step does not undo the run.
+    if not main():
+        sys.exit(STOPPED_EXIT_CODE)